@@ -0,0 +1,14 @@
+CREATE TABLE price_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    product_id INTEGER NOT NULL,
+    old_price REAL,
+    new_price REAL NOT NULL,
+    source TEXT NOT NULL,
+    changed_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_price_history_product ON price_history(product_id, changed_at);
+
+-- Seed the series with the current price of every existing product
+INSERT INTO price_history (product_id, old_price, new_price, source, changed_at)
+SELECT id, NULL, price, 'initial', CAST(strftime('%s', 'now') AS INTEGER) FROM products;
@@ -0,0 +1,15 @@
+CREATE TABLE product_media (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    product_id INTEGER NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    file_key TEXT NOT NULL,
+    alt_text TEXT,
+    position INTEGER NOT NULL,
+    is_primary BOOLEAN NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL,
+    UNIQUE (product_id, file_key)
+);
+
+CREATE INDEX idx_product_media_file ON product_media(file_key);
+
+-- At most one primary image per product
+CREATE UNIQUE INDEX idx_product_media_primary ON product_media(product_id) WHERE is_primary;
@@ -0,0 +1,12 @@
+-- Resumable uploads in progress; the bytes received so far live in the upload quarantine
+CREATE TABLE upload_sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    owner TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    received INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_upload_sessions_expiry ON upload_sessions(expires_at);
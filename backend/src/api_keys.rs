@@ -0,0 +1,269 @@
+use crate::auth::{generate_token, hash_token};
+use crate::rbac::Permission;
+use crate::{now_unix, telemetry, AppState};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    // Reserved for read-only product routes that need a key; catalog reads are public today, so
+    // it grants nothing yet. Still accepted so keys and integrations asking for it keep working.
+    #[serde(rename = "products:read")]
+    ProductsRead,
+    #[serde(rename = "products:write")]
+    ProductsWrite,
+    #[serde(rename = "files:read")]
+    FilesRead,
+    #[serde(rename = "files:write")]
+    FilesWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ProductsRead => "products:read",
+            Scope::ProductsWrite => "products:write",
+            Scope::FilesRead => "files:read",
+            Scope::FilesWrite => "files:write",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Scope> {
+        match value.trim() {
+            "products:read" => Some(Scope::ProductsRead),
+            "products:write" => Some(Scope::ProductsWrite),
+            "files:read" => Some(Scope::FilesRead),
+            "files:write" => Some(Scope::FilesWrite),
+            _ => None,
+        }
+    }
+
+    // Generator control and user management are never delegated to keys, and `products:read`
+    // has no permission to grant until some product read requires one
+    pub fn grants(&self, permission: Permission) -> bool {
+        matches!(
+            (self, permission),
+            (Scope::ProductsWrite, Permission::ProductsWrite)
+                | (Scope::FilesRead, Permission::FilesRead)
+                | (Scope::FilesWrite, Permission::FilesWrite)
+        )
+    }
+}
+
+fn parse_scopes(value: &str) -> Vec<Scope> {
+    value.split(',').filter_map(Scope::parse).collect()
+}
+
+fn join_scopes(scopes: &[Scope]) -> String {
+    scopes.iter().map(Scope::as_str).collect::<Vec<_>>().join(",")
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+// Keys look like `ek_<prefix>_<secret>`; the prefix is kept in clear text so keys can be told apart
+fn generate_key() -> (String, String) {
+    let token = generate_token();
+    let prefix = token[..8].to_string();
+    (format!("ek_{}_{}", prefix, &token[8..]), prefix)
+}
+
+pub fn api_key(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("ApiKey "))
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+}
+
+pub struct ApiKeyCaller {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+// Looks up an active key and records that it was used
+pub async fn find_api_key(db_pool: &sqlx::SqlitePool, key: &str) -> Option<ApiKeyCaller> {
+    let key_hash = hash_token(key);
+    let now = now_unix();
+
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_keys SET last_used_at = ?
+        WHERE key_hash = ? AND revoked_at IS NULL
+        RETURNING id AS "id!", name AS "name!", scopes AS "scopes!"
+        "#,
+        now,
+        key_hash
+    )
+    .fetch_optional(db_pool)
+    .instrument(telemetry::db_span("UPDATE", "api_keys"))
+    .await
+    .ok()
+    .flatten()?;
+
+    Some(ApiKeyCaller {
+        id: row.id,
+        name: row.name,
+        scopes: parse_scopes(&row.scopes),
+    })
+}
+
+pub async fn create_api_key(
+    data: web::Data<AppState>,
+    request: web::Json<CreateApiKeyRequest>,
+) -> impl Responder {
+    let name = request.name.trim().to_string();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "API key name cannot be empty"
+        }));
+    }
+    if request.scopes.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "API key needs at least one scope"
+        }));
+    }
+
+    let (key, prefix) = generate_key();
+    let key_hash = hash_token(&key);
+    let scopes = join_scopes(&request.scopes);
+    let created_at = now_unix();
+
+    match sqlx::query!(
+        r#"
+        INSERT INTO api_keys (name, prefix, key_hash, scopes, created_at)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+        name,
+        prefix,
+        key_hash,
+        scopes,
+        created_at
+    )
+    .execute(&data.db_pool)
+    .instrument(telemetry::db_span("INSERT", "api_keys"))
+    .await
+    {
+        Ok(result) => HttpResponse::Created().json(serde_json::json!({
+            "key": key,
+            "api_key": ApiKey {
+                id: result.last_insert_rowid(),
+                name,
+                prefix,
+                scopes: request.scopes.clone(),
+                created_at,
+                last_used_at: None,
+                revoked_at: None,
+            }
+        })),
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to create API key"
+        })),
+    }
+}
+
+pub async fn list_api_keys(data: web::Data<AppState>) -> impl Responder {
+    match sqlx::query!(
+        r#"
+        SELECT id AS "id!", name, prefix, scopes, created_at, last_used_at, revoked_at
+        FROM api_keys
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "api_keys"))
+    .await
+    {
+        Ok(rows) => {
+            let keys = rows
+                .into_iter()
+                .map(|row| ApiKey {
+                    id: row.id,
+                    name: row.name,
+                    prefix: row.prefix,
+                    scopes: parse_scopes(&row.scopes),
+                    created_at: row.created_at,
+                    last_used_at: row.last_used_at,
+                    revoked_at: row.revoked_at,
+                })
+                .collect::<Vec<_>>();
+            HttpResponse::Ok().json(keys)
+        }
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to list API keys"
+        })),
+    }
+}
+
+// Replaces the secret of an active key, invalidating the previous one immediately
+pub async fn rotate_api_key(data: web::Data<AppState>, id: web::Path<i64>) -> impl Responder {
+    let key_id = id.into_inner();
+    let (key, prefix) = generate_key();
+    let key_hash = hash_token(&key);
+
+    match sqlx::query!(
+        r#"
+        UPDATE api_keys SET prefix = ?, key_hash = ?, last_used_at = NULL
+        WHERE id = ? AND revoked_at IS NULL
+        "#,
+        prefix,
+        key_hash,
+        key_id
+    )
+    .execute(&data.db_pool)
+    .instrument(telemetry::db_span("UPDATE", "api_keys"))
+    .await
+    {
+        Ok(result) if result.rows_affected() > 0 => HttpResponse::Ok().json(serde_json::json!({
+            "id": key_id,
+            "key": key,
+            "prefix": prefix
+        })),
+        Ok(_) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "API key not found"
+        })),
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to rotate API key"
+        })),
+    }
+}
+
+pub async fn revoke_api_key(data: web::Data<AppState>, id: web::Path<i64>) -> impl Responder {
+    let key_id = id.into_inner();
+    let now = now_unix();
+
+    match sqlx::query!(
+        r#"UPDATE api_keys SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL"#,
+        now,
+        key_id
+    )
+    .execute(&data.db_pool)
+    .instrument(telemetry::db_span("UPDATE", "api_keys"))
+    .await
+    {
+        Ok(result) if result.rows_affected() > 0 => HttpResponse::NoContent().finish(),
+        Ok(_) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "API key not found"
+        })),
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to revoke API key"
+        })),
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{now_unix, telemetry, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::Instrument;
+
+// Actor recorded for changes made by the background product generator
+pub const GENERATOR_ACTOR: &str = "system:generator";
+
+// Actor recorded for repricing done by the price-drift simulator
+pub const SIMULATOR_ACTOR: &str = "system:simulator";
+
+// Actor recorded for files removed by the orphaned upload cleanup
+pub const CLEANUP_ACTOR: &str = "system:cleanup";
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub entity_type: String,
+    pub entity_id: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub diff: Value,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub entity_type: Option<String>,
+    pub entity_id: Option<String>,
+    pub actor: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+// Field-level changes between two JSON objects as `{ field: { before, after } }`
+pub fn diff(before: Option<&Value>, after: Option<&Value>) -> Value {
+    let empty = Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut changes = Map::new();
+    for key in before.keys().chain(after.keys()) {
+        if changes.contains_key(key) {
+            continue;
+        }
+        let old = before.get(key).unwrap_or(&Value::Null);
+        let new = after.get(key).unwrap_or(&Value::Null);
+        if old != new {
+            changes.insert(
+                key.clone(),
+                serde_json::json!({ "before": old, "after": new }),
+            );
+        }
+    }
+    Value::Object(changes)
+}
+
+// Writes an audit record; failures are logged rather than failing the mutation that was already applied
+pub async fn record(
+    db_pool: &sqlx::SqlitePool,
+    actor: &str,
+    action: &str,
+    entity_type: &str,
+    entity_id: &str,
+    before: Option<&Value>,
+    after: Option<&Value>,
+) {
+    let diff_json = diff(before, after).to_string();
+    let before_json = before.map(Value::to_string);
+    let after_json = after.map(Value::to_string);
+    let created_at = now_unix();
+
+    if let Err(e) = sqlx::query!(
+        r#"
+        INSERT INTO audit_log (actor, action, entity_type, entity_id, before_json, after_json, diff_json, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+        actor,
+        action,
+        entity_type,
+        entity_id,
+        before_json,
+        after_json,
+        diff_json,
+        created_at
+    )
+    .execute(db_pool)
+    .instrument(telemetry::db_span("INSERT", "audit_log"))
+    .await
+    {
+        tracing::error!(entity_type, entity_id, error = %e, "Failed to write audit record");
+    }
+}
+
+fn parse_json(value: Option<String>) -> Option<Value> {
+    value.and_then(|json| serde_json::from_str(&json).ok())
+}
+
+pub async fn list_audit(data: web::Data<AppState>, query: web::Query<AuditQuery>) -> impl Responder {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    match sqlx::query!(
+        r#"
+        SELECT id AS "id!", actor, action, entity_type, entity_id, before_json, after_json, diff_json, created_at
+        FROM audit_log
+        WHERE (?1 IS NULL OR entity_type = ?1)
+          AND (?2 IS NULL OR entity_id = ?2)
+          AND (?3 IS NULL OR actor = ?3)
+          AND (?4 IS NULL OR created_at >= ?4)
+          AND (?5 IS NULL OR created_at <= ?5)
+        ORDER BY id DESC
+        LIMIT ?6 OFFSET ?7
+        "#,
+        query.entity_type,
+        query.entity_id,
+        query.actor,
+        query.from,
+        query.to,
+        limit,
+        offset
+    )
+    .fetch_all(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "audit_log"))
+    .await
+    {
+        Ok(rows) => {
+            let entries = rows
+                .into_iter()
+                .map(|row| AuditEntry {
+                    id: row.id,
+                    actor: row.actor,
+                    action: row.action,
+                    entity_type: row.entity_type,
+                    entity_id: row.entity_id,
+                    before: parse_json(row.before_json),
+                    after: parse_json(row.after_json),
+                    diff: parse_json(Some(row.diff_json)).unwrap_or(Value::Null),
+                    created_at: row.created_at,
+                })
+                .collect::<Vec<_>>();
+            HttpResponse::Ok().json(entries)
+        }
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to load audit log"
+        })),
+    }
+}
@@ -0,0 +1,277 @@
+use crate::models::{LoginRequest, RegisterRequest, User};
+use crate::rbac::Role;
+use crate::{now_unix, telemetry, validation, AppState};
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, Responder};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use futures::future::LocalBoxFuture;
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
+
+// Sessions are valid for a week unless revoked earlier
+const SESSION_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+// Verified against when the email is unknown so both login failures cost one Argon2 run;
+// uses the same parameters as `Argon2::default()`
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$c9LPebkY0nIss62k6Gp9Iw$DVqICe73zveBUHVPzUL6mwLyKD4519uLNGOzuZfTDvI";
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Tokens are only ever stored as their SHA-256 digest
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+fn unauthorized(message: &str) -> actix_web::Error {
+    InternalError::from_response(
+        message.to_string(),
+        HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": message
+        })),
+    )
+    .into()
+}
+
+// Extractor resolving the session token in the `Authorization: Bearer` header to a user
+pub struct AuthUser {
+    pub id: i64,
+    pub email: String,
+    pub role: Role,
+    pub created_at: i64,
+    pub session_id: i64,
+}
+
+impl FromRequest for AuthUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = bearer_token(req);
+        let app_state = req.app_data::<web::Data<AppState>>().cloned();
+
+        Box::pin(async move {
+            let token = token.ok_or_else(|| unauthorized("Missing bearer token"))?;
+            let app_state = app_state.ok_or_else(|| unauthorized("Authentication is not configured"))?;
+            find_session_user(&app_state.db_pool, &token)
+                .await
+                .ok_or_else(|| unauthorized("Invalid or expired token"))
+        })
+    }
+}
+
+pub async fn find_session_user(db_pool: &sqlx::SqlitePool, token: &str) -> Option<AuthUser> {
+    let token_hash = hash_token(token);
+    let now = now_unix();
+
+    sqlx::query_as!(
+        AuthUser,
+        r#"
+        SELECT users.id AS "id!", users.email, users.role AS "role: Role", users.created_at, sessions.id AS "session_id!"
+        FROM sessions
+        JOIN users ON users.id = sessions.user_id
+        WHERE sessions.token_hash = ? AND sessions.revoked_at IS NULL AND sessions.expires_at > ?
+        "#,
+        token_hash,
+        now
+    )
+    .fetch_optional(db_pool)
+    .instrument(telemetry::db_span("SELECT", "sessions"))
+    .await
+    .ok()
+    .flatten()
+}
+
+pub async fn register(
+    data: web::Data<AppState>,
+    request: web::Json<RegisterRequest>,
+) -> impl Responder {
+    if let Err(e) = validation::validate_registration(&request) {
+        return e.error_response();
+    }
+
+    let request = request.into_inner();
+    let email = request.email.trim().to_lowercase();
+    let password_hash = match web::block(move || hash_password(&request.password)).await {
+        Ok(Ok(hash)) => hash,
+        _ => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to hash password"
+            }))
+        }
+    };
+    let created_at = now_unix();
+
+    match sqlx::query!(
+        r#"INSERT INTO users (email, password_hash, created_at) VALUES (?, ?, ?)"#,
+        email,
+        password_hash,
+        created_at
+    )
+    .execute(&data.db_pool)
+    .instrument(telemetry::db_span("INSERT", "users"))
+    .await
+    {
+        Ok(result) => HttpResponse::Created().json(User {
+            id: result.last_insert_rowid(),
+            email,
+            role: Role::Customer,
+            created_at,
+        }),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().json(serde_json::json!({
+                "error": "Email is already registered"
+            }))
+        }
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to register user"
+        })),
+    }
+}
+
+pub async fn login(data: web::Data<AppState>, request: web::Json<LoginRequest>) -> impl Responder {
+    let request = request.into_inner();
+    let email = request.email.trim().to_lowercase();
+
+    let user = match sqlx::query!(
+        r#"SELECT id AS "id!", email, password_hash, role AS "role: Role", created_at FROM users WHERE email = ?"#,
+        email
+    )
+    .fetch_optional(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "users"))
+    .await
+    {
+        Ok(user) => user,
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Database error"
+            }))
+        }
+    };
+
+    let password_hash = user
+        .as_ref()
+        .map_or_else(|| DUMMY_PASSWORD_HASH.to_string(), |user| user.password_hash.clone());
+    let verified = web::block(move || verify_password(&request.password, &password_hash))
+        .await
+        .unwrap_or(false);
+    let Some(user) = user.filter(|_| verified) else {
+        return HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Invalid email or password"
+        }));
+    };
+
+    let token = generate_token();
+    let token_hash = hash_token(&token);
+    let created_at = now_unix();
+    let expires_at = created_at + SESSION_TTL_SECS;
+
+    match sqlx::query!(
+        r#"
+        INSERT INTO sessions (user_id, token_hash, created_at, expires_at)
+        VALUES (?, ?, ?, ?)
+        "#,
+        user.id,
+        token_hash,
+        created_at,
+        expires_at
+    )
+    .execute(&data.db_pool)
+    .instrument(telemetry::db_span("INSERT", "sessions"))
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
+            "token": token,
+            "token_type": "Bearer",
+            "expires_at": expires_at,
+            "user": User {
+                id: user.id,
+                email: user.email,
+                role: user.role,
+                created_at: user.created_at,
+            }
+        })),
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to create session"
+        })),
+    }
+}
+
+// Revokes the session the request was made with
+pub async fn logout(data: web::Data<AppState>, user: AuthUser) -> impl Responder {
+    let now = now_unix();
+
+    match sqlx::query!(
+        r#"UPDATE sessions SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL"#,
+        now,
+        user.session_id
+    )
+    .execute(&data.db_pool)
+    .instrument(telemetry::db_span("UPDATE", "sessions"))
+    .await
+    {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to revoke session"
+        })),
+    }
+}
+
+// Revokes every active session of the current user
+pub async fn logout_all(data: web::Data<AppState>, user: AuthUser) -> impl Responder {
+    let now = now_unix();
+
+    match sqlx::query!(
+        r#"UPDATE sessions SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL"#,
+        now,
+        user.id
+    )
+    .execute(&data.db_pool)
+    .instrument(telemetry::db_span("UPDATE", "sessions"))
+    .await
+    {
+        Ok(result) => HttpResponse::Ok().json(serde_json::json!({
+            "revoked": result.rows_affected()
+        })),
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to revoke sessions"
+        })),
+    }
+}
+
+pub async fn me(user: AuthUser) -> impl Responder {
+    HttpResponse::Ok().json(User {
+        id: user.id,
+        email: user.email,
+        role: user.role,
+        created_at: user.created_at,
+    })
+}
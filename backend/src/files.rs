@@ -0,0 +1,1145 @@
+use crate::rbac::Principal;
+use crate::auth::generate_token;
+use crate::images::{self, ImageConfig};
+use crate::scanner::ScanVerdict;
+use crate::storage::{is_valid_key, BlobObject, PresignedRequest, PRESIGN_EXPIRY};
+use crate::{audit, media, now_unix, telemetry, AppState};
+use actix_files::NamedFile;
+use actix_multipart::{Field, Multipart};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType, EntityTag, IfNoneMatch};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use futures::StreamExt;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tracing::Instrument;
+
+// Types accepted for upload, keyed by what their leading bytes say they are
+const ALLOWED_TYPES: [(&str, &str); 8] = [
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("application/pdf", "pdf"),
+    ("text/plain", "txt"),
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+];
+
+// Enough of the file to recognize every allowed type
+const SNIFF_LEN: usize = 8192;
+
+// Presigned uploads land under this prefix and stay unusable until they are completed
+const PENDING_PREFIX: &str = "pending-";
+
+// Scratch space inside the upload root, so finished files can be renamed into place atomically.
+// It doubles as the quarantine: uploads sit here until scanned, and keys can never reach it.
+const TEMP_DIR: &str = ".tmp";
+
+pub struct UploadConfig {
+    pub dir: PathBuf,
+    pub max_file_size: u64,
+    pub max_request_size: u64,
+    pub images: ImageConfig,
+    // How long an upload may sit unattached before the cleanup removes it
+    pub orphan_grace: Duration,
+    // Resumable uploads may be far larger than a multipart request, but arrive in bounded chunks
+    pub max_resumable_size: u64,
+    pub max_chunk_size: u64,
+    // How long a resumable upload may go without a chunk before it is dropped
+    pub session_ttl: Duration,
+}
+
+impl UploadConfig {
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| {
+            std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+        };
+        Self {
+            dir: PathBuf::from(std::env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string())),
+            max_file_size: number("UPLOAD_MAX_FILE_BYTES", 10 * 1024 * 1024),
+            max_request_size: number("UPLOAD_MAX_REQUEST_BYTES", 50 * 1024 * 1024),
+            images: ImageConfig::from_env(),
+            orphan_grace: Duration::from_secs(number("UPLOAD_ORPHAN_GRACE_SECS", 24 * 60 * 60)),
+            max_resumable_size: number("UPLOAD_MAX_RESUMABLE_BYTES", 2 * 1024 * 1024 * 1024),
+            max_chunk_size: number("UPLOAD_MAX_CHUNK_BYTES", 8 * 1024 * 1024),
+            session_ttl: Duration::from_secs(number("UPLOAD_SESSION_TTL_SECS", 24 * 60 * 60)),
+        }
+    }
+}
+
+// Detects the MIME type from the file's leading bytes; text has no signature, so it is
+// recognized as valid UTF-8 without NUL bytes
+pub fn sniff_content_type(head: &[u8]) -> Option<(&'static str, &'static str)> {
+    let detected = match infer::get(head) {
+        Some(kind) => kind.mime_type(),
+        None => {
+            // The head may end partway through a multi-byte character
+            let text = match std::str::from_utf8(head) {
+                Ok(text) => text,
+                Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or(""),
+                Err(_) => return None,
+            };
+            if head.is_empty() || text.contains('\0') {
+                return None;
+            }
+            "text/plain"
+        }
+    };
+    ALLOWED_TYPES.iter().copied().find(|(mime, _)| *mime == detected)
+}
+
+// Keeps only the final path component of a client-supplied name, for display and audit only
+pub fn display_name(original: Option<&str>) -> String {
+    let name = original
+        .unwrap_or("")
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or("")
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        "unnamed".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// Removes the temp file once it is no longer needed; after a move into the store there is nothing left to remove
+struct TempFile {
+    path: PathBuf,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub fn is_pending_key(key: &str) -> bool {
+    key.starts_with(PENDING_PREFIX)
+}
+
+pub fn quarantine_dir(upload_dir: &Path) -> PathBuf {
+    upload_dir.join(TEMP_DIR)
+}
+
+fn temp_path(upload_dir: &Path) -> PathBuf {
+    let suffix: String = rand::thread_rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect();
+    quarantine_dir(upload_dir).join(format!("upload-{}", suffix))
+}
+
+pub struct UploadError {
+    status: StatusCode,
+    message: String,
+}
+
+impl UploadError {
+    fn new(status: StatusCode, message: String) -> Self {
+        Self { status, message }
+    }
+
+    pub fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(serde_json::json!({
+            "error": self.message
+        }))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoredFile {
+    // Row in the `files` table, assigned once the file is stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub key: String,
+    pub original_name: String,
+    pub content_type: &'static str,
+    pub size: u64,
+    pub sha256: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<StoredVariant>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoredVariant {
+    pub key: String,
+    pub size_name: String,
+    pub width: u32,
+    pub content_type: &'static str,
+    pub size: u64,
+}
+
+// A fully received and checked upload still sitting in the temp directory
+struct StagedFile {
+    temp: TempFile,
+    stored: StoredFile,
+    variants: Vec<(TempFile, StoredVariant)>,
+}
+
+// Streams one multipart field to a temp file, stopping as soon as either limit is crossed
+async fn stage_field(
+    field: &mut Field,
+    config: &UploadConfig,
+    request_size: &mut u64,
+) -> Result<StagedFile, UploadError> {
+    let Some(filename) = field.content_disposition().get_filename() else {
+        return Err(UploadError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Part '{}' is not a file; every part of an upload must be one", field.name()),
+        ));
+    };
+    let original_name = display_name(Some(filename));
+    let temp = TempFile { path: temp_path(&config.dir) };
+    let mut file = tokio::fs::File::create(&temp.path).await.map_err(|e| {
+        UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create file: {}", e))
+    })?;
+
+    let mut hasher = Sha256::new();
+    let mut head: Vec<u8> = Vec::with_capacity(SNIFF_LEN);
+    let mut size: u64 = 0;
+    while let Some(chunk) = field.next().await {
+        let data: Bytes = chunk
+            .map_err(|e| UploadError::new(StatusCode::BAD_REQUEST, format!("Failed to read chunk: {}", e)))?;
+
+        size += data.len() as u64;
+        *request_size += data.len() as u64;
+        if size > config.max_file_size {
+            return Err(UploadError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("'{}' exceeds the {} byte limit per file", original_name, config.max_file_size),
+            ));
+        }
+        if *request_size > config.max_request_size {
+            return Err(UploadError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Upload exceeds the {} byte limit per request", config.max_request_size),
+            ));
+        }
+
+        if head.len() < SNIFF_LEN {
+            let take = (SNIFF_LEN - head.len()).min(data.len());
+            head.extend_from_slice(&data[..take]);
+        }
+        hasher.update(&data);
+        file.write_all(&data).await.map_err(|e| {
+            UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write chunk: {}", e))
+        })?;
+    }
+    file.sync_all().await.map_err(|e| {
+        UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file: {}", e))
+    })?;
+
+    let Some((content_type, extension)) = sniff_content_type(&head) else {
+        return Err(UploadError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("'{}' is not an allowed file type", original_name),
+        ));
+    };
+
+    // Identical content always lands on the same key, so re-uploads never clobber other files
+    let sha256 = hex::encode(hasher.finalize());
+    Ok(StagedFile {
+        temp,
+        stored: StoredFile {
+            id: None,
+            key: format!("{}.{}", sha256, extension),
+            original_name,
+            content_type,
+            size,
+            sha256,
+            variants: vec![],
+        },
+        variants: vec![],
+    })
+}
+
+async fn write_temp(upload_dir: &Path, bytes: &[u8]) -> Result<TempFile, UploadError> {
+    let temp = TempFile { path: temp_path(upload_dir) };
+    tokio::fs::write(&temp.path, bytes).await.map_err(|e| {
+        UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file: {}", e))
+    })?;
+    Ok(temp)
+}
+
+// Strips EXIF from a staged image and renders its size and format variants. The original is
+// replaced by the stripped copy, so its hash and key describe what is actually stored.
+async fn process_image(file: &mut StagedFile, config: &UploadConfig) -> Result<(), UploadError> {
+    let content_type = file.stored.content_type;
+    let bytes = tokio::fs::read(&file.temp.path).await.map_err(|e| {
+        UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read file: {}", e))
+    })?;
+    let image_config = config.images.clone();
+    let processed = web::block(move || {
+        let stripped = images::strip_metadata(bytes, content_type)?;
+        let variants = images::render_variants(&stripped, content_type, &image_config)?;
+        Ok::<_, String>((stripped, variants))
+    })
+    .await;
+    let (stripped, variants) = match processed {
+        Ok(Ok(processed)) => processed,
+        Ok(Err(e)) => {
+            return Err(UploadError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("'{}' could not be decoded as an image: {}", file.stored.original_name, e),
+            ));
+        }
+        Err(e) => {
+            return Err(UploadError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to process image: {}", e),
+            ));
+        }
+    };
+
+    // A fresh file rather than a rewrite, since the staged one may be linked to a resumable upload
+    file.temp = write_temp(&config.dir, &stripped).await?;
+    let extension = file.stored.key.rsplit_once('.').map_or("", |(_, extension)| extension).to_string();
+    file.stored.sha256 = hex::encode(Sha256::digest(&stripped));
+    file.stored.key = format!("{}.{}", file.stored.sha256, extension);
+    file.stored.size = stripped.len() as u64;
+
+    for variant in variants {
+        let temp = write_temp(&config.dir, &variant.bytes).await?;
+        let stored = StoredVariant {
+            key: images::variant_key(&file.stored.key, &variant.size, variant.extension),
+            size_name: variant.size,
+            width: variant.width,
+            content_type: variant.content_type,
+            size: variant.bytes.len() as u64,
+        };
+        file.variants.push((temp, stored));
+    }
+    Ok(())
+}
+
+// Identical content shares one row, which keeps the first uploader as its owner
+async fn record_file(conn: &mut sqlx::SqliteConnection, file: &StoredFile, owner: &str) -> Result<i64, sqlx::Error> {
+    let variant_keys = serde_json::to_string(&file.variants.iter().map(|v| &v.key).collect::<Vec<_>>())
+        .unwrap_or_else(|_| "[]".to_string());
+    let size = file.size as i64;
+    let created_at = now_unix();
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO files (key, original_name, content_type, size, sha256, variant_keys, owner, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (key) DO UPDATE SET variant_keys = excluded.variant_keys
+        RETURNING id AS "id!"
+        "#,
+        file.key,
+        file.original_name,
+        file.content_type,
+        size,
+        file.sha256,
+        variant_keys,
+        owner,
+        created_at
+    )
+    .fetch_one(conn)
+    .instrument(telemetry::db_span("INSERT", "files"))
+    .await
+}
+
+// Accepts one or more files; nothing is stored unless every file in the request is accepted
+pub async fn upload_file(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    principal: Principal,
+    mut payload: Multipart,
+) -> impl Responder {
+    let config = &data.uploads;
+
+    // Refuse oversized requests up front when the client says how big they are
+    let declared_size = req
+        .headers()
+        .get("Content-Length")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared_size.is_some_and(|size| size > config.max_request_size) {
+        return UploadError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Upload exceeds the {} byte limit per request", config.max_request_size),
+        )
+        .error_response();
+    }
+
+    // Create uploads directory if it doesn't exist
+    if let Err(e) = fs::create_dir_all(quarantine_dir(&config.dir)) {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to create upload directory: {}", e)
+        }));
+    }
+
+    let mut staged = vec![];
+    let mut request_size = 0;
+    while let Some(item) = payload.next().await {
+        let mut field = match item {
+            Ok(field) => field,
+            Err(e) => {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Failed to process upload: {}", e)
+                }));
+            }
+        };
+        // Returning drops everything staged so far, which deletes the temp files
+        match stage_field(&mut field, config, &mut request_size).await {
+            Ok(file) => staged.push(file),
+            Err(e) => return e.error_response(),
+        }
+    }
+
+    if staged.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "No file was uploaded"
+        }));
+    }
+
+    match store_staged(&data, &principal, staged).await {
+        Ok(files) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "message": format!("Uploaded {} file(s)", files.len()),
+            "files": files
+        })),
+        Err(response) => response,
+    }
+}
+
+// Stores a file assembled in the quarantine by other means, such as a resumable upload.
+// The source stays in place, so a failed attempt can be retried.
+pub async fn store_assembled(
+    data: &AppState,
+    principal: &Principal,
+    source: &Path,
+    original_name: String,
+    expected_sha256: Option<&str>,
+) -> Result<StoredFile, HttpResponse> {
+    let internal = |e: std::io::Error| {
+        HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to read upload: {}", e)
+        }))
+    };
+    let temp = TempFile { path: temp_path(&data.uploads.dir) };
+    fs::hard_link(source, &temp.path).map_err(internal)?;
+
+    let path = temp.path.clone();
+    let inspected = web::block(move || -> std::io::Result<(String, Vec<u8>, u64)> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut size = 0;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            if head.len() < SNIFF_LEN {
+                let take = (SNIFF_LEN - head.len()).min(read);
+                head.extend_from_slice(&buffer[..take]);
+            }
+            hasher.update(&buffer[..read]);
+            size += read as u64;
+        }
+        Ok((hex::encode(hasher.finalize()), head, size))
+    })
+    .await;
+    let (sha256, head, size) = match inspected {
+        Ok(inspected) => inspected.map_err(internal)?,
+        Err(e) => return Err(internal(std::io::Error::other(e))),
+    };
+
+    if expected_sha256.is_some_and(|expected| !expected.eq_ignore_ascii_case(&sha256)) {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Assembled file does not match the expected sha256"
+        })));
+    }
+    let Some((content_type, extension)) = sniff_content_type(&head) else {
+        return Err(UploadError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("'{}' is not an allowed file type", original_name),
+        )
+        .error_response());
+    };
+    // Images are decoded in memory, so only resumable uploads of other types may exceed the per-file limit
+    if images::is_processable(content_type) && size > data.uploads.max_file_size {
+        return Err(UploadError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Images must not exceed the {} byte limit per file", data.uploads.max_file_size),
+        )
+        .error_response());
+    }
+
+    let staged = StagedFile {
+        temp,
+        stored: StoredFile {
+            id: None,
+            key: format!("{}.{}", sha256, extension),
+            original_name,
+            content_type,
+            size,
+            sha256,
+            variants: vec![],
+        },
+        variants: vec![],
+    };
+    let mut files = store_staged(data, principal, vec![staged]).await?;
+    Ok(files.remove(0))
+}
+
+// Scans, processes and stores staged files, then records them. Every file is scanned before
+// anything decodes it, and one rejection or storage failure fails the whole batch.
+async fn store_staged(
+    data: &AppState,
+    principal: &Principal,
+    mut staged: Vec<StagedFile>,
+) -> Result<Vec<StoredFile>, HttpResponse> {
+    let mut rejected = vec![];
+    for file in &staged {
+        match data.scanner.scan(&file.temp.path).await {
+            Ok(ScanVerdict::Clean) => {}
+            Ok(ScanVerdict::Rejected(reason)) => {
+                tracing::warn!(
+                    original_name = %file.stored.original_name,
+                    sha256 = %file.stored.sha256,
+                    actor = %principal.name,
+                    %reason,
+                    "Rejected upload"
+                );
+                rejected.push(serde_json::json!({
+                    "original_name": file.stored.original_name,
+                    "sha256": file.stored.sha256,
+                    "reason": reason
+                }));
+            }
+            Err(e) => {
+                tracing::error!(original_name = %file.stored.original_name, error = %e, "Could not scan upload");
+                return Err(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                    "error": "Uploads cannot be scanned right now; try again later"
+                })));
+            }
+        }
+    }
+    if !rejected.is_empty() {
+        for rejection in &rejected {
+            audit::record(&data.db_pool, &principal.name, "reject", "file", "upload", None, Some(rejection)).await;
+        }
+        return Err(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "error": format!("{} file(s) failed the malware scan", rejected.len()),
+            "rejected": rejected
+        })));
+    }
+
+    for file in &mut staged {
+        if images::is_processable(file.stored.content_type) {
+            process_image(file, &data.uploads).await.map_err(|e| e.error_response())?;
+        }
+    }
+
+    // Keys are content addressed, so one that is already stored holds the same bytes and is
+    // left alone on rollback; only objects this batch added, or that could not be checked, are removed again
+    let mut written = vec![];
+    let mut stored = async {
+        let mut files = vec![];
+        for mut file in staged {
+            // Variants go in first, so a stored original always has them alongside
+            let keys = file.variants.iter().map(|(temp, variant)| (&variant.key, &temp.path, variant.content_type));
+            for (key, path, content_type) in keys.chain([(&file.stored.key, &file.temp.path, file.stored.content_type)]) {
+                let existed = matches!(data.blobs.exists(key).await, Ok(true));
+                data.blobs.put_file(key, path, content_type).await?;
+                if !existed && !written.contains(key) {
+                    written.push(key.clone());
+                }
+            }
+            file.stored.variants = file.variants.into_iter().map(|(_, variant)| variant).collect();
+            files.push(file.stored);
+        }
+        Ok::<_, std::io::Error>(files)
+    }
+    .await
+    .map_err(|e| {
+        HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to store file: {}", e)
+        }))
+    });
+
+    // Rows go in together, so a failure records none of the batch
+    if let Ok(files) = &mut stored {
+        let recorded = async {
+            let mut transaction = data.db_pool.begin().await?;
+            for file in files.iter_mut() {
+                file.id = Some(record_file(&mut transaction, file, &principal.name).await?);
+            }
+            transaction.commit().await
+        }
+        .await;
+        if recorded.is_err() {
+            stored = Err(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to record file"
+            })));
+        }
+    }
+
+    let files = match stored {
+        Ok(files) => files,
+        Err(response) => {
+            for key in &written {
+                if let Err(e) = data.blobs.delete(key).await {
+                    tracing::error!(%key, error = %e, "Could not remove file from a failed upload");
+                }
+            }
+            return Err(response);
+        }
+    };
+    for file in &files {
+        data.metrics.upload_bytes.inc_by(file.size);
+        audit::record(
+            &data.db_pool,
+            &principal.name,
+            "upload",
+            "file",
+            &file.key,
+            None,
+            serde_json::to_value(file).ok().as_ref(),
+        )
+        .await;
+    }
+    Ok(files)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImageQuery {
+    pub w: Option<u32>,
+    pub format: Option<String>,
+}
+
+// A single `bytes=` range in a shape every backend understands; anything else is served whole
+fn single_byte_range(req: &HttpRequest) -> Option<&str> {
+    let range = req.headers().get("Range")?.to_str().ok()?.trim();
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let number = |value: &str| value.parse::<u64>().ok();
+    match (number(start), number(end)) {
+        (Some(start), Some(end)) if start <= end => Some(range),
+        (Some(_), None) if end.is_empty() => Some(range),
+        (None, Some(length)) if start.is_empty() && length > 0 => Some(range),
+        _ => None,
+    }
+}
+
+fn etag_matches(req: &HttpRequest, etag: &str) -> bool {
+    let Ok(etag) = etag.parse::<EntityTag>() else {
+        return false;
+    };
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(&etag)),
+        None => false,
+    }
+}
+
+async fn open_object(data: &AppState, key: &str, range: Option<&str>) -> std::io::Result<Option<BlobObject>> {
+    match range {
+        Some(range) => data.blobs.open_range(key, range).await,
+        None => data.blobs.open(key).await,
+    }
+}
+
+// Local files get NamedFile's chunked reads plus Range, If-None-Match and If-Modified-Since;
+// objects from other backends are streamed through with the range forwarded to the backend and
+// If-None-Match checked against its ETag. Images can be requested at `?w=` and `?format=`, which
+// serve the closest pre-rendered variant.
+pub async fn download_file(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    filename: web::Path<String>,
+    query: web::Query<ImageQuery>,
+) -> HttpResponse {
+    let mut filename = filename.into_inner();
+    let range = single_byte_range(&req);
+    let mut variant_object = None;
+    if query.w.is_some() || query.format.is_some() {
+        match images::resolve_variant(&filename, query.w, query.format.as_deref(), &data.uploads.images) {
+            // Originals stored before variants existed are served as they are
+            Ok(Some(variant)) => {
+                if let Ok(Some(object)) = open_object(&data, &variant, range).await {
+                    variant_object = Some(object);
+                    filename = variant;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": e
+                }));
+            }
+        }
+    }
+
+    let not_found = || {
+        HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("File '{}' not found", filename)
+        }))
+    };
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(filename.to_string())],
+    };
+
+    // Presigned uploads are unchecked until completed
+    if is_pending_key(&filename) {
+        return not_found();
+    }
+
+    let object = match variant_object {
+        Some(object) => object,
+        None => match open_object(&data, &filename, range).await {
+            Ok(Some(object)) => object,
+            Ok(None) => return not_found(),
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => {
+                return HttpResponse::RangeNotSatisfiable().json(serde_json::json!({
+                    "error": "Requested range is not satisfiable"
+                }));
+            }
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to open file: {}", e)
+                }));
+            }
+        },
+    };
+
+    match object {
+        BlobObject::File(filepath) => match NamedFile::open_async(&filepath).await {
+            Ok(file) => file
+                .use_etag(true)
+                .use_last_modified(true)
+                .set_content_disposition(disposition)
+                .into_response(&req),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => not_found(),
+            Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to open file: {}", e)
+            })),
+        },
+        BlobObject::Stream { content_type, size, etag, content_range, body } => {
+            if let Some(etag) = etag.as_deref().filter(|etag| etag_matches(&req, etag)) {
+                return HttpResponse::NotModified().insert_header(("ETag", etag)).finish();
+            }
+            let mut response = match &content_range {
+                Some(_) => HttpResponse::PartialContent(),
+                None => HttpResponse::Ok(),
+            };
+            response
+                .content_type(content_type.unwrap_or_else(|| "application/octet-stream".to_string()))
+                .insert_header(disposition)
+                .insert_header(("Accept-Ranges", "bytes"));
+            if let Some(etag) = etag {
+                response.insert_header(("ETag", etag));
+            }
+            if let Some(content_range) = content_range {
+                response.insert_header(("Content-Range", content_range));
+            }
+            match size {
+                Some(size) => response.no_chunking(size).streaming(body),
+                None => response.streaming(body),
+            }
+        }
+    }
+}
+
+fn presigned_response(key: &str, request: PresignedRequest) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "key": key,
+        "method": request.method,
+        "url": request.url,
+        "headers": request.headers,
+        "expires_in": PRESIGN_EXPIRY.as_secs()
+    }))
+}
+
+fn presign_unsupported() -> HttpResponse {
+    HttpResponse::NotImplemented().json(serde_json::json!({
+        "error": "The storage backend does not support presigned URLs"
+    }))
+}
+
+// A time-limited URL the client can fetch the file from directly, when the backend supports it
+pub async fn presign_download(data: web::Data<AppState>, key: web::Path<String>) -> impl Responder {
+    if !is_valid_key(&key) || is_pending_key(&key) {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("File '{}' not found", key)
+        }));
+    }
+    match data.blobs.presign_download(&key, &key).await {
+        Ok(Some(request)) => presigned_response(&key, request),
+        Ok(None) => presign_unsupported(),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to presign download: {}", e)
+        })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PresignUploadRequest {
+    pub content_type: String,
+    pub size: u64,
+}
+
+// Lets a client send one file straight to the storage backend; the key is chosen here, and the
+// signed type and length keep the upload within the same allow-list and size limit. The object
+// lands under a pending key and only becomes a file once `complete_presigned_upload` accepts it.
+pub async fn presign_upload(
+    data: web::Data<AppState>,
+    principal: Principal,
+    request: web::Json<PresignUploadRequest>,
+) -> impl Responder {
+    let Some((content_type, extension)) = ALLOWED_TYPES.iter().copied().find(|(mime, _)| *mime == request.content_type)
+    else {
+        return UploadError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("'{}' is not an allowed file type", request.content_type),
+        )
+        .error_response();
+    };
+    if request.size == 0 || request.size > data.uploads.max_file_size {
+        return UploadError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Files must be between 1 and {} bytes", data.uploads.max_file_size),
+        )
+        .error_response();
+    }
+
+    let key = format!("{}{}.{}", PENDING_PREFIX, generate_token(), extension);
+    match data.blobs.presign_upload(&key, content_type, request.size).await {
+        Ok(Some(presigned)) => {
+            audit::record(
+                &data.db_pool,
+                &principal.name,
+                "presign_upload",
+                "file",
+                &key,
+                None,
+                Some(&serde_json::json!({ "key": key, "content_type": content_type, "size": request.size })),
+            )
+            .await;
+            presigned_response(&key, presigned)
+        }
+        Ok(None) => presign_unsupported(),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to presign upload: {}", e)
+        })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompletePresignedRequest {
+    pub original_name: Option<String>,
+}
+
+fn pending_not_found(key: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": format!("Presigned upload '{}' not found", key)
+    }))
+}
+
+// Copies a pending object into the quarantine, refusing it once it passes the per-file limit
+async fn fetch_pending(data: &AppState, key: &str) -> Result<Option<TempFile>, UploadError> {
+    let internal = |e: std::io::Error| {
+        UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read upload: {}", e))
+    };
+    let too_large = || {
+        UploadError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Upload exceeds the {} byte limit per file", data.uploads.max_file_size),
+        )
+    };
+    let Some(object) = data.blobs.open(key).await.map_err(internal)? else {
+        return Ok(None);
+    };
+
+    tokio::fs::create_dir_all(quarantine_dir(&data.uploads.dir)).await.map_err(internal)?;
+    let temp = TempFile { path: temp_path(&data.uploads.dir) };
+    match object {
+        BlobObject::File(path) => {
+            if tokio::fs::metadata(&path).await.map_err(internal)?.len() > data.uploads.max_file_size {
+                return Err(too_large());
+            }
+            tokio::fs::copy(&path, &temp.path).await.map_err(internal)?;
+        }
+        BlobObject::Stream { size, mut body, .. } => {
+            if size.is_some_and(|size| size > data.uploads.max_file_size) {
+                return Err(too_large());
+            }
+            let mut file = tokio::fs::File::create(&temp.path).await.map_err(internal)?;
+            let mut received = 0;
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(internal)?;
+                received += chunk.len() as u64;
+                if received > data.uploads.max_file_size {
+                    return Err(too_large());
+                }
+                file.write_all(&chunk).await.map_err(internal)?;
+            }
+            file.sync_all().await.map_err(internal)?;
+        }
+    }
+    Ok(Some(temp))
+}
+
+async fn remove_pending(data: &AppState, key: &str) {
+    if let Err(e) = data.blobs.delete(key).await {
+        tracing::error!(%key, error = %e, "Could not remove a presigned upload");
+    }
+}
+
+// Runs a presigned upload through the regular upload pipeline, which scans, sniffs, processes and
+// records it under its content key. A file that is refused is removed from the store; a temporary
+// failure leaves it in place, so completing can be retried.
+pub async fn complete_presigned_upload(
+    data: web::Data<AppState>,
+    principal: Principal,
+    key: web::Path<String>,
+    request: Option<web::Json<CompletePresignedRequest>>,
+) -> impl Responder {
+    let key = key.into_inner();
+    if !is_valid_key(&key) || !is_pending_key(&key) {
+        return pending_not_found(&key);
+    }
+
+    let source = match fetch_pending(&data, &key).await {
+        Ok(Some(source)) => source,
+        Ok(None) => return pending_not_found(&key),
+        Err(e) => {
+            if e.status.is_client_error() {
+                remove_pending(&data, &key).await;
+            }
+            return e.error_response();
+        }
+    };
+    let original_name = display_name(request.as_ref().and_then(|request| request.original_name.as_deref()));
+    match store_assembled(&data, &principal, &source.path, original_name, None).await {
+        Ok(file) => {
+            remove_pending(&data, &key).await;
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "message": "Uploaded 1 file(s)",
+                "files": [file]
+            }))
+        }
+        Err(response) => {
+            if response.status().is_client_error() {
+                remove_pending(&data, &key).await;
+            }
+            response
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileRecord {
+    pub id: i64,
+    pub key: String,
+    pub original_name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub sha256: String,
+    pub variant_keys: Vec<String>,
+    pub owner: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileQuery {
+    pub content_type: Option<String>,
+    pub owner: Option<String>,
+    // Matched anywhere in the original file name
+    pub search: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+struct FileRow {
+    id: i64,
+    key: String,
+    original_name: String,
+    content_type: String,
+    size: i64,
+    sha256: String,
+    variant_keys: String,
+    owner: String,
+    created_at: i64,
+}
+
+impl From<FileRow> for FileRecord {
+    fn from(row: FileRow) -> Self {
+        Self {
+            id: row.id,
+            key: row.key,
+            original_name: row.original_name,
+            content_type: row.content_type,
+            size: row.size,
+            sha256: row.sha256,
+            variant_keys: serde_json::from_str(&row.variant_keys).unwrap_or_default(),
+            owner: row.owner,
+            created_at: row.created_at,
+        }
+    }
+}
+
+// Newest first, with the total matching count so clients can page through. Only uploads recorded in
+// `files` are listed; files stored before that table existed stay on disk but cannot be listed or
+// deleted through the API.
+pub async fn list_files(data: web::Data<AppState>, query: web::Query<FileQuery>) -> impl Responder {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) FROM files
+        WHERE (?1 IS NULL OR content_type = ?1)
+          AND (?2 IS NULL OR owner = ?2)
+          AND (?3 IS NULL OR instr(lower(original_name), lower(?3)) > 0)
+          AND (?4 IS NULL OR created_at >= ?4)
+          AND (?5 IS NULL OR created_at <= ?5)
+        "#,
+        query.content_type,
+        query.owner,
+        query.search,
+        query.from,
+        query.to
+    )
+    .fetch_one(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "files"))
+    .await;
+
+    let rows = sqlx::query_as!(
+        FileRow,
+        r#"
+        SELECT id AS "id!", key, original_name, content_type, size, sha256, variant_keys, owner, created_at
+        FROM files
+        WHERE (?1 IS NULL OR content_type = ?1)
+          AND (?2 IS NULL OR owner = ?2)
+          AND (?3 IS NULL OR instr(lower(original_name), lower(?3)) > 0)
+          AND (?4 IS NULL OR created_at >= ?4)
+          AND (?5 IS NULL OR created_at <= ?5)
+        ORDER BY id DESC
+        LIMIT ?6 OFFSET ?7
+        "#,
+        query.content_type,
+        query.owner,
+        query.search,
+        query.from,
+        query.to,
+        limit,
+        offset
+    )
+    .fetch_all(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "files"))
+    .await;
+
+    match (total, rows) {
+        (Ok(total), Ok(rows)) => {
+            let files = rows.into_iter().map(FileRecord::from).collect::<Vec<_>>();
+            HttpResponse::Ok().json(serde_json::json!({
+                "files": files,
+                "total": total,
+                "offset": offset,
+                "limit": limit
+            }))
+        }
+        _ => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to list files"
+        })),
+    }
+}
+
+// Removes a file and its variants, unless a product still shows it
+pub async fn delete_file(data: web::Data<AppState>, principal: Principal, id: web::Path<i64>) -> impl Responder {
+    let file_id = id.into_inner();
+    let file: FileRecord = match sqlx::query_as!(
+        FileRow,
+        r#"
+        SELECT id AS "id!", key, original_name, content_type, size, sha256, variant_keys, owner, created_at
+        FROM files WHERE id = ?
+        "#,
+        file_id
+    )
+    .fetch_optional(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "files"))
+    .await
+    {
+        Ok(Some(row)) => row.into(),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "File not found"
+            }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to load file"
+            }));
+        }
+    };
+
+    // Products can show a file through their gallery or through an image URL set by hand
+    let gallery = sqlx::query_scalar!("SELECT product_id FROM product_media WHERE file_key = ?", file.key)
+        .fetch_all(&data.db_pool)
+        .instrument(telemetry::db_span("SELECT", "product_media"))
+        .await;
+    let images = media::products_showing(&data.db_pool, &file.key).await;
+    let product_ids = match (gallery, images) {
+        (Ok(gallery), Ok(images)) => gallery.into_iter().chain(images).collect::<BTreeSet<_>>(),
+        _ => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to check file references"
+            }));
+        }
+    };
+    if !product_ids.is_empty() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "error": "File is still used by products",
+            "product_ids": product_ids
+        }));
+    }
+
+    // Variants first, so a failure never leaves them without their original
+    for key in file.variant_keys.iter().chain([&file.key]) {
+        if let Err(e) = data.blobs.delete(key).await {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to delete file: {}", e)
+            }));
+        }
+    }
+    if sqlx::query!("DELETE FROM files WHERE id = ?", file_id)
+        .execute(&data.db_pool)
+        .instrument(telemetry::db_span("DELETE", "files"))
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to delete file"
+        }));
+    }
+
+    audit::record(
+        &data.db_pool,
+        &principal.name,
+        "delete",
+        "file",
+        &file.key,
+        serde_json::to_value(&file).ok().as_ref(),
+        None,
+    )
+    .await;
+    HttpResponse::NoContent().finish()
+}
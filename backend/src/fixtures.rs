@@ -0,0 +1,325 @@
+use crate::files::{self, UploadConfig};
+use crate::models::CreateProductRequest;
+use crate::storage::{self, BlobStore};
+use crate::{now_unix, telemetry};
+use image::{ImageFormat, Rgb, RgbImage};
+use rand::distributions::WeightedIndex;
+use rand::prelude::Distribution;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::Instrument;
+
+struct CategoryCatalog {
+    name: &'static str,
+    nouns: &'static [&'static str],
+    min_price: f64,
+    max_price: f64,
+}
+
+const CATALOG: [CategoryCatalog; 5] = [
+    CategoryCatalog {
+        name: "Electronics",
+        nouns: &["Wireless Headphones", "Smart Watch", "Bluetooth Speaker", "USB-C Charger", "Mechanical Keyboard", "4K Monitor"],
+        min_price: 19.0,
+        max_price: 899.0,
+    },
+    CategoryCatalog {
+        name: "Books",
+        nouns: &["Cookbook", "Mystery Novel", "Travel Guide", "Poetry Collection", "Science Primer", "Graphic Novel"],
+        min_price: 6.0,
+        max_price: 59.0,
+    },
+    CategoryCatalog {
+        name: "Clothing",
+        nouns: &["Hoodie", "Denim Jacket", "Running Shoes", "Wool Scarf", "Linen Shirt", "Rain Coat"],
+        min_price: 12.0,
+        max_price: 249.0,
+    },
+    CategoryCatalog {
+        name: "Home",
+        nouns: &["Table Lamp", "Throw Blanket", "Ceramic Vase", "Chef Knife", "Coffee Grinder", "Storage Basket"],
+        min_price: 9.0,
+        max_price: 349.0,
+    },
+    CategoryCatalog {
+        name: "Toys",
+        nouns: &["Building Blocks", "Puzzle Set", "Plush Bear", "Model Train", "Board Game", "Kite"],
+        min_price: 5.0,
+        max_price: 149.0,
+    },
+];
+
+const ADJECTIVES: [&str; 10] = [
+    "Classic", "Premium", "Compact", "Deluxe", "Everyday", "Vintage", "Eco", "Pro", "Essential", "Signature",
+];
+
+const QUALITIES: [&str; 6] = [
+    "durable materials",
+    "a timeless design",
+    "thoughtful details",
+    "everyday comfort",
+    "excellent value",
+    "careful craftsmanship",
+];
+
+// Builds realistic-looking products; the same seed always yields the same sequence
+pub struct ProductFactory {
+    rng: StdRng,
+}
+
+impl ProductFactory {
+    pub fn seeded(seed: u64) -> Self {
+        Self { rng: StdRng::seed_from_u64(seed) }
+    }
+
+    pub fn from_entropy() -> Self {
+        Self { rng: StdRng::from_entropy() }
+    }
+
+    pub fn pick_category(&mut self, mix: Option<&HashMap<String, u32>>) -> String {
+        if let Some(mix) = mix {
+            // Sorted so a seeded factory does not depend on map iteration order
+            let mut categories = mix.iter().collect::<Vec<_>>();
+            categories.sort();
+            if let Ok(weights) = WeightedIndex::new(categories.iter().map(|(_, weight)| **weight)) {
+                return categories[weights.sample(&mut self.rng)].0.clone();
+            }
+        }
+        CATALOG[self.rng.gen_range(0..CATALOG.len())].name.to_string()
+    }
+
+    pub fn next_product(&mut self, category: &str) -> CreateProductRequest {
+        let catalog = CATALOG.iter().find(|c| c.name.eq_ignore_ascii_case(category));
+        let nouns = catalog.map_or(&["Item", "Gift Set", "Bundle"][..], |c| c.nouns);
+        let (min_price, max_price) = catalog.map_or((5.0, 199.0), |c| (c.min_price, c.max_price));
+
+        let adjective = ADJECTIVES[self.rng.gen_range(0..ADJECTIVES.len())];
+        let noun = nouns[self.rng.gen_range(0..nouns.len())];
+        let quality = QUALITIES[self.rng.gen_range(0..QUALITIES.len())];
+        // Prices end in .99 or .49 like a real shop's
+        let cents = if self.rng.gen_bool(0.7) { 0.99 } else { 0.49 };
+        let price = self.rng.gen_range(min_price..max_price).floor() + cents;
+        let name = format!("{} {}", adjective, noun);
+
+        CreateProductRequest {
+            description: Some(format!(
+                "The {} brings {} to your {} collection.",
+                name,
+                quality,
+                category.to_lowercase()
+            )),
+            // Images come from the media gallery; seeding attaches placeholders afterwards
+            image: None,
+            name,
+            price,
+            category: category.to_string(),
+        }
+    }
+}
+
+// Inserts `count` seeded products along with the first point of their price series
+pub async fn seed_products(db_pool: &sqlx::SqlitePool, count: u64, seed: u64) -> Result<u64, sqlx::Error> {
+    let mut factory = ProductFactory::seeded(seed);
+    let created_at = now_unix();
+    let mut transaction = db_pool.begin().await?;
+
+    for _ in 0..count {
+        let category = factory.pick_category(None);
+        let product = factory.next_product(&category);
+        let product_id = sqlx::query!(
+            r#"
+            INSERT INTO products (name, price, image, description, category)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+            product.name,
+            product.price,
+            product.image,
+            product.description,
+            product.category
+        )
+        .execute(&mut *transaction)
+        .instrument(telemetry::db_span("INSERT", "products"))
+        .await?
+        .last_insert_rowid();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO price_history (product_id, old_price, new_price, source, changed_at)
+            VALUES (?, NULL, ?, 'seed', ?)
+            "#,
+            product_id,
+            product.price,
+            created_at
+        )
+        .execute(&mut *transaction)
+        .instrument(telemetry::db_span("INSERT", "price_history"))
+        .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(count)
+}
+
+const PLACEHOLDER_SIZE: u32 = 96;
+
+// A flat swatch whose colour comes from the category name, so every run draws the same bytes
+fn placeholder_png(category: &str) -> Result<Vec<u8>, String> {
+    let digest = Sha256::digest(category.to_lowercase().as_bytes());
+    let swatch = RgbImage::from_pixel(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE, Rgb([digest[0], digest[1], digest[2]]));
+    let mut bytes = vec![];
+    swatch
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+// Stores one placeholder per category and makes it the primary gallery image of every product
+// in that category, recorded in `files` like any upload so downloads and cleanup treat it the same
+pub async fn attach_placeholder_media(
+    db_pool: &sqlx::SqlitePool,
+    blobs: &dyn BlobStore,
+    upload_dir: &Path,
+) -> Result<u64, String> {
+    let products = sqlx::query!(r#"SELECT id AS "id!", name, category FROM products ORDER BY id"#)
+        .fetch_all(db_pool)
+        .instrument(telemetry::db_span("SELECT", "products"))
+        .await
+        .map_err(|e| e.to_string())?;
+    let categories: BTreeSet<String> = products.iter().filter_map(|p| p.category.clone()).collect();
+
+    let scratch = files::quarantine_dir(upload_dir);
+    tokio::fs::create_dir_all(&scratch).await.map_err(|e| e.to_string())?;
+    let mut keys = HashMap::new();
+    for category in categories {
+        let bytes = placeholder_png(&category)?;
+        let sha256 = hex::encode(Sha256::digest(&bytes));
+        let key = format!("{}.png", sha256);
+        let temp = scratch.join(format!("seed-{}", key));
+        tokio::fs::write(&temp, &bytes).await.map_err(|e| e.to_string())?;
+        let stored = blobs.put_file(&key, &temp, "image/png").await;
+        let _ = tokio::fs::remove_file(&temp).await;
+        stored.map_err(|e| format!("Failed to store placeholder for {}: {}", category, e))?;
+
+        let original_name = format!("{}.png", category.to_lowercase());
+        let size = bytes.len() as i64;
+        let created_at = now_unix();
+        sqlx::query!(
+            r#"
+            INSERT INTO files (key, original_name, content_type, size, sha256, variant_keys, owner, created_at)
+            VALUES (?, ?, 'image/png', ?, ?, '[]', 'seed', ?)
+            ON CONFLICT (key) DO NOTHING
+            "#,
+            key,
+            original_name,
+            size,
+            sha256,
+            created_at
+        )
+        .execute(db_pool)
+        .instrument(telemetry::db_span("INSERT", "files"))
+        .await
+        .map_err(|e| e.to_string())?;
+        keys.insert(category, key);
+    }
+
+    let created_at = now_unix();
+    let mut attached = 0;
+    let mut transaction = db_pool.begin().await.map_err(|e| e.to_string())?;
+    for product in &products {
+        let Some(key) = product.category.as_ref().and_then(|category| keys.get(category)) else {
+            continue;
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO product_media (product_id, file_key, alt_text, position, is_primary, created_at)
+            VALUES (?, ?, ?, 0, 1, ?)
+            "#,
+            product.id,
+            key,
+            product.name,
+            created_at
+        )
+        .execute(&mut *transaction)
+        .instrument(telemetry::db_span("INSERT", "product_media"))
+        .await
+        .map_err(|e| e.to_string())?;
+        let image = format!("/api/download/{}", key);
+        sqlx::query!("UPDATE products SET image = ? WHERE id = ?", image, product.id)
+            .execute(&mut *transaction)
+            .instrument(telemetry::db_span("UPDATE", "products"))
+            .await
+            .map_err(|e| e.to_string())?;
+        attached += 1;
+    }
+    transaction.commit().await.map_err(|e| e.to_string())?;
+    Ok(attached)
+}
+
+const SEED_USAGE: &str = "Usage: backend seed [--count N] [--seed S] [--database PATH]";
+
+// `backend seed` fills a fresh database with a reproducible catalog
+pub async fn run_seed_command(args: &[String]) -> Result<(), String> {
+    let mut count: u64 = 50;
+    let mut seed: u64 = 42;
+    let mut database = String::from("products.db");
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("Missing value for {}\n{}", name, SEED_USAGE))
+        };
+        match arg.as_str() {
+            "--count" => count = value("--count")?.parse().map_err(|_| format!("--count must be a number\n{}", SEED_USAGE))?,
+            "--seed" => seed = value("--seed")?.parse().map_err(|_| format!("--seed must be a number\n{}", SEED_USAGE))?,
+            "--database" => database = value("--database")?,
+            other => return Err(format!("Unknown argument '{}'\n{}", other, SEED_USAGE)),
+        }
+    }
+
+    let options = SqliteConnectOptions::from_str(&format!("sqlite:{}", database))
+        .map_err(|e| e.to_string())?
+        .create_if_missing(true);
+    let db_pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", database, e))?;
+    sqlx::migrate!()
+        .run(&db_pool)
+        .await
+        .map_err(|e| format!("Failed to run migrations: {}", e))?;
+
+    let existing = sqlx::query_scalar!("SELECT COUNT(*) FROM products")
+        .fetch_one(&db_pool)
+        .instrument(telemetry::db_span("SELECT", "products"))
+        .await
+        .map_err(|e| e.to_string())?;
+    if existing > 0 {
+        return Err(format!(
+            "{} already contains {} products; seed only fills a fresh database",
+            database, existing
+        ));
+    }
+
+    let inserted = seed_products(&db_pool, count, seed)
+        .await
+        .map_err(|e| format!("Failed to seed products: {}", e))?;
+    let uploads = UploadConfig::from_env();
+    let blobs = storage::from_env(&uploads.dir);
+    let attached = attach_placeholder_media(&db_pool, blobs.as_ref(), &uploads.dir)
+        .await
+        .map_err(|e| format!("Failed to attach placeholder images: {}", e))?;
+    println!(
+        "Seeded {} products into {} with seed {}, {} with placeholder images.",
+        inserted, database, seed, attached
+    );
+    Ok(())
+}
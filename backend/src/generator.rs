@@ -0,0 +1,391 @@
+use crate::models::{GenerationMode, GeneratorConfig, Product, ProductEvent};
+use crate::rbac::Principal;
+use crate::{audit, now_unix, pricing, telemetry, validation, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use crate::fixtures::ProductFactory;
+use rand::Rng;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use tracing::Instrument;
+
+pub fn simulate_price_change(price: f64, volatility: f64) -> f64 {
+    let mut rng = rand::thread_rng();
+    let change_percentage = rng.gen_range(-volatility..=volatility); // Random change between -volatility% and +volatility%
+    let new_price = price * (1.0 + change_percentage / 100.0);
+    ((new_price * 100.0).round() / 100.0).max(0.01)
+}
+
+// Inserts up to `count` new products and returns how many were stored
+async fn generate_batch(
+    app_state: &AppState,
+    config: &GeneratorConfig,
+    factory: &mut ProductFactory,
+    count: u64,
+) -> u64 {
+    let mut generated = 0;
+
+    for _ in 0..count {
+        let category = factory.pick_category(config.category_mix.as_ref());
+        let new_product = factory.next_product(&category);
+
+        // Insert into database
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO products (name, price, image, description, category)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+            new_product.name,
+            new_product.price,
+            new_product.image,
+            new_product.description,
+            new_product.category
+        )
+        .execute(&app_state.db_pool)
+        .instrument(telemetry::db_span("INSERT", "products"))
+        .await;
+
+        if let Ok(result) = inserted {
+            let stored = Product {
+                id: result.last_insert_rowid(),
+                name: new_product.name,
+                price: new_product.price,
+                image: new_product.image,
+                description: new_product.description,
+                category: Some(new_product.category),
+            };
+            audit::record(
+                &app_state.db_pool,
+                audit::GENERATOR_ACTOR,
+                "create",
+                "product",
+                &stored.id.to_string(),
+                None,
+                serde_json::to_value(&stored).ok().as_ref(),
+            )
+            .await;
+            pricing::record_price(app_state, &stored, None, "generator").await;
+            generated += 1;
+
+            // Broadcast the new product to all connected WebSocket clients
+            app_state.events.publish(ProductEvent::ProductCreated(stored));
+        }
+    }
+
+    generated
+}
+
+// Reprices up to `count` existing products and returns how many were changed; a batch with
+// nothing to reprice is an error, since every later batch would find nothing either
+async fn reprice_batch(app_state: &AppState, config: &GeneratorConfig, count: u64) -> Result<u64, String> {
+    let limit = count as i64;
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, name, price, image, description, category FROM products
+        WHERE (?1 IS NULL OR category = ?1)
+        ORDER BY RANDOM()
+        LIMIT ?2
+        "#,
+        config.category,
+        limit
+    )
+    .fetch_all(&app_state.db_pool)
+    .instrument(telemetry::db_span("SELECT", "products"))
+    .await
+    .map_err(|e| format!("Failed to load products to reprice: {}", e))?;
+    if rows.is_empty() {
+        return Err(match &config.category {
+            Some(category) => format!("No products in category '{}' to reprice", category),
+            None => "No products to reprice".to_string(),
+        });
+    }
+
+    let mut repriced = 0;
+    for row in rows {
+        let before = Product {
+            id: row.id,
+            name: row.name,
+            price: row.price,
+            image: row.image,
+            description: row.description,
+            category: row.category,
+        };
+        let new_price = simulate_price_change(before.price, config.volatility);
+
+        let updated = sqlx::query!(
+            r#"UPDATE products SET price = ? WHERE id = ?"#,
+            new_price,
+            before.id
+        )
+        .execute(&app_state.db_pool)
+        .instrument(telemetry::db_span("UPDATE", "products"))
+        .await;
+        if !matches!(updated, Ok(result) if result.rows_affected() > 0) {
+            continue;
+        }
+
+        let after = Product { price: new_price, ..before.clone() };
+        audit::record(
+            &app_state.db_pool,
+            audit::SIMULATOR_ACTOR,
+            "update",
+            "product",
+            &before.id.to_string(),
+            serde_json::to_value(&before).ok().as_ref(),
+            serde_json::to_value(&after).ok().as_ref(),
+        )
+        .await;
+        pricing::record_price(app_state, &after, Some(before.price), "simulator").await;
+        app_state.events.publish(ProductEvent::ProductUpdated(after));
+        repriced += 1;
+    }
+
+    Ok(repriced)
+}
+
+#[derive(Default)]
+struct GeneratorCounters {
+    generated: AtomicU64,
+    repriced: AtomicU64,
+}
+
+// Kept up to date by the task, so a finished run can be told apart from a crashed or stuck one
+#[derive(Default)]
+struct RunHealth {
+    finished: AtomicBool,
+    last_batch_at: AtomicI64,
+    // Why the run gave up, when it ended without being stopped or reaching its maximum
+    failure: std::sync::Mutex<Option<String>>,
+}
+
+struct GeneratorRun {
+    config: GeneratorConfig,
+    started_at: i64,
+    started: Instant,
+    processed: Arc<AtomicU64>,
+    health: Arc<RunHealth>,
+    stop_tx: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeneratorStatus {
+    pub running: bool,
+    pub config: Option<GeneratorConfig>,
+    pub started_at: Option<i64>,
+    // Items produced by the current (or last) run
+    pub processed: u64,
+    pub total_generated: u64,
+    pub total_repriced: u64,
+    pub configured_rate_per_sec: f64,
+    pub observed_rate_per_sec: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Owns the one background generator task; starting again replaces the running task
+#[derive(Default)]
+pub struct GeneratorController {
+    run: Mutex<Option<GeneratorRun>>,
+    counters: Arc<GeneratorCounters>,
+}
+
+impl GeneratorController {
+    pub async fn start(&self, app_state: web::Data<AppState>, config: GeneratorConfig) {
+        let mut run = self.run.lock().await;
+        if let Some(previous) = run.take() {
+            Self::shut_down(previous).await;
+        }
+
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let processed = Arc::new(AtomicU64::new(0));
+        let health = Arc::new(RunHealth::default());
+        health.last_batch_at.store(now_unix(), Ordering::Relaxed);
+        let handle = tokio::spawn(run_generator(
+            app_state,
+            config.clone(),
+            processed.clone(),
+            self.counters.clone(),
+            health.clone(),
+            stop_rx,
+        ));
+
+        *run = Some(GeneratorRun {
+            config,
+            started_at: now_unix(),
+            started: Instant::now(),
+            processed,
+            health,
+            stop_tx,
+            handle,
+        });
+    }
+
+    // Signals the task and waits for it to finish its current batch; returns whether it was running
+    pub async fn stop(&self) -> bool {
+        let mut run = self.run.lock().await;
+        match run.take() {
+            Some(previous) => {
+                let was_running = !previous.handle.is_finished();
+                Self::shut_down(previous).await;
+                was_running
+            }
+            None => false,
+        }
+    }
+
+    async fn shut_down(run: GeneratorRun) {
+        let _ = run.stop_tx.send(true);
+        let _ = run.handle.await;
+    }
+
+    // Fine when idle or when the last run ended on its own; a task that died or stopped making
+    // progress for ten intervals (at least a minute) is reported
+    pub async fn health(&self) -> Result<(), String> {
+        let run = self.run.lock().await;
+        let Some(run) = run.as_ref() else {
+            return Ok(());
+        };
+        if let Some(failure) = run.health.failure.lock().unwrap().clone() {
+            return Err(failure);
+        }
+        if run.handle.is_finished() {
+            return if run.health.finished.load(Ordering::Relaxed) {
+                Ok(())
+            } else {
+                Err("generator task stopped unexpectedly".to_string())
+            };
+        }
+
+        let stalled_for = now_unix() - run.health.last_batch_at.load(Ordering::Relaxed);
+        let limit = (run.config.interval_ms / 100).max(60) as i64;
+        if stalled_for > limit {
+            return Err(format!("generator made no progress for {}s", stalled_for));
+        }
+        Ok(())
+    }
+
+    pub async fn status(&self) -> GeneratorStatus {
+        let run = self.run.lock().await;
+        let total_generated = self.counters.generated.load(Ordering::Relaxed);
+        let total_repriced = self.counters.repriced.load(Ordering::Relaxed);
+
+        match run.as_ref() {
+            Some(run) => {
+                let running = !run.handle.is_finished();
+                let processed = run.processed.load(Ordering::Relaxed);
+                let elapsed = run.started.elapsed().as_secs_f64();
+                GeneratorStatus {
+                    running,
+                    config: Some(run.config.clone()),
+                    started_at: Some(run.started_at),
+                    processed,
+                    total_generated,
+                    total_repriced,
+                    configured_rate_per_sec: if running {
+                        run.config.batch_size as f64 * 1000.0 / run.config.interval_ms as f64
+                    } else {
+                        0.0
+                    },
+                    observed_rate_per_sec: if elapsed > 0.0 { processed as f64 / elapsed } else { 0.0 },
+                    error: run.health.failure.lock().unwrap().clone(),
+                }
+            }
+            None => GeneratorStatus {
+                running: false,
+                config: None,
+                started_at: None,
+                processed: 0,
+                total_generated,
+                total_repriced,
+                configured_rate_per_sec: 0.0,
+                observed_rate_per_sec: 0.0,
+                error: None,
+            },
+        }
+    }
+}
+
+async fn run_generator(
+    app_state: web::Data<AppState>,
+    config: GeneratorConfig,
+    processed: Arc<AtomicU64>,
+    counters: Arc<GeneratorCounters>,
+    health: Arc<RunHealth>,
+    mut stop_rx: watch::Receiver<bool>,
+) {
+    tracing::info!(mode = ?config.mode, "Started generator");
+    let mut factory = match config.seed {
+        Some(seed) => ProductFactory::seeded(seed),
+        None => ProductFactory::from_entropy(),
+    };
+
+    loop {
+        health.last_batch_at.store(now_unix(), Ordering::Relaxed);
+        let done = processed.load(Ordering::Relaxed);
+        let remaining = config.max_count.map_or(u64::MAX, |max| max.saturating_sub(done));
+        if remaining == 0 {
+            tracing::info!("Generator reached its maximum count");
+            break;
+        }
+
+        let count = remaining.min(config.batch_size as u64);
+        let produced = match config.mode {
+            GenerationMode::Generate => {
+                let generated = generate_batch(&app_state, &config, &mut factory, count).await;
+                counters.generated.fetch_add(generated, Ordering::Relaxed);
+                generated
+            }
+            GenerationMode::PriceDrift => match reprice_batch(&app_state, &config, count).await {
+                Ok(repriced) => {
+                    counters.repriced.fetch_add(repriced, Ordering::Relaxed);
+                    repriced
+                }
+                Err(reason) => {
+                    tracing::warn!(%reason, "Generator run failed");
+                    *health.failure.lock().unwrap() = Some(reason);
+                    break;
+                }
+            },
+        };
+        processed.fetch_add(produced, Ordering::Relaxed);
+        app_state.metrics.generator_products.with_label_values(&[config.mode.as_str()]).inc_by(produced);
+
+        tokio::select! {
+            _ = sleep(Duration::from_millis(config.interval_ms)) => {}
+            _ = stop_rx.changed() => break,
+        }
+    }
+    health.finished.store(true, Ordering::Relaxed);
+    tracing::info!("Stopped generator");
+}
+
+pub async fn start_generator(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    config: web::Json<GeneratorConfig>,
+) -> impl Responder {
+    let config = config.into_inner();
+    if let Err(e) = validation::validate_generator_config(&config) {
+        return e.error_response();
+    }
+
+    tracing::info!(mode = ?config.mode, actor = %principal.name, "Generator start requested");
+    app_state.generator.start(app_state.clone(), config).await;
+    HttpResponse::Ok().json(app_state.generator.status().await)
+}
+
+pub async fn stop_generator(app_state: web::Data<AppState>, principal: Principal) -> impl Responder {
+    if app_state.generator.stop().await {
+        tracing::info!(actor = %principal.name, "Generator stop requested");
+    }
+    HttpResponse::Ok().json(app_state.generator.status().await)
+}
+
+pub async fn generator_status(app_state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(app_state.generator.status().await)
+}
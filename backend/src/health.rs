@@ -0,0 +1,112 @@
+use crate::files::quarantine_dir;
+use crate::{storage, telemetry, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+// A dependency that does not answer within this long counts as degraded
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize)]
+pub struct CheckResult {
+    pub status: &'static str,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+async fn run_check<F: Future<Output = Result<(), String>>>(check: F) -> CheckResult {
+    let started = Instant::now();
+    let outcome = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(format!("no answer within {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    CheckResult {
+        status: if outcome.is_ok() { "ok" } else { "degraded" },
+        latency_ms: started.elapsed().as_millis() as u64,
+        error: outcome.err(),
+    }
+}
+
+async fn check_database(data: &AppState) -> Result<(), String> {
+    sqlx::query!("SELECT 1 AS one")
+        .fetch_one(&data.db_pool)
+        .instrument(telemetry::db_span("SELECT", "1"))
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// The server runs migrations on startup, so anything missing means the schema was changed underneath it
+async fn check_migrations(data: &AppState) -> Result<(), String> {
+    let applied = sqlx::query_scalar!(r#"SELECT version AS "version!" FROM _sqlx_migrations WHERE success = 1"#)
+        .fetch_all(&data.db_pool)
+        .instrument(telemetry::db_span("SELECT", "_sqlx_migrations"))
+        .await
+        .map_err(|e| e.to_string())?;
+    let pending = sqlx::migrate!()
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration() && !applied.contains(&migration.version))
+        .map(|migration| migration.version.to_string())
+        .collect::<Vec<_>>();
+    if !pending.is_empty() {
+        return Err(format!("pending migrations: {}", pending.join(", ")));
+    }
+    Ok(())
+}
+
+// Uploads are staged on local disk before reaching the store, so both have to accept writes
+async fn check_storage(data: &AppState) -> Result<(), String> {
+    let staging = quarantine_dir(&data.uploads.dir);
+    web::block(move || storage::write_probe(&staging))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("upload staging directory: {}", e))?;
+    data.blobs.check().await.map_err(|e| format!("storage backend: {}", e))
+}
+
+async fn readiness_checks(data: &AppState) -> BTreeMap<&'static str, CheckResult> {
+    let (database, migrations, storage, generator) = futures::join!(
+        run_check(check_database(data)),
+        run_check(check_migrations(data)),
+        run_check(check_storage(data)),
+        run_check(data.generator.health()),
+    );
+    BTreeMap::from([
+        ("database", database),
+        ("migrations", migrations),
+        ("storage", storage),
+        ("generator", generator),
+    ])
+}
+
+// Answers as long as the process can serve requests; dependencies are left to readiness
+pub async fn live() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok"
+    }))
+}
+
+pub async fn ready(data: web::Data<AppState>) -> impl Responder {
+    let checks = readiness_checks(&data).await;
+    let ready = checks.values().all(CheckResult::is_ok);
+    let body = serde_json::json!({
+        "status": if ready { "ok" } else { "degraded" },
+        "checks": checks
+    });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        tracing::warn!(checks = %body["checks"], "Readiness check failed");
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
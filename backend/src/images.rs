@@ -0,0 +1,219 @@
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use img_parts::{DynImage, ImageEXIF};
+use std::io::Cursor;
+
+// Formats variants can be rendered in besides the original's own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariantFormat {
+    WebP,
+    Avif,
+}
+
+impl VariantFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "webp" => Some(Self::WebP),
+            "avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::WebP => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::WebP => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ImageConfig {
+    // Named maximum widths, e.g. thumbnail 160, card 480, full 1280
+    pub sizes: Vec<(String, u32)>,
+    pub formats: Vec<VariantFormat>,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            sizes: vec![
+                ("thumbnail".to_string(), 160),
+                ("card".to_string(), 480),
+                ("full".to_string(), 1280),
+            ],
+            formats: vec![VariantFormat::WebP, VariantFormat::Avif],
+        }
+    }
+}
+
+impl ImageConfig {
+    // `IMAGE_SIZES=thumbnail:160,card:480` and `IMAGE_FORMATS=webp,avif` override the defaults
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(value) = std::env::var("IMAGE_SIZES") {
+            config.sizes = value
+                .split(',')
+                .filter_map(|entry| {
+                    let (name, width) = entry.trim().split_once(':')?;
+                    let width = width.trim().parse().ok().filter(|width| *width > 0)?;
+                    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric());
+                    valid.then(|| (name.to_lowercase(), width))
+                })
+                .collect();
+        }
+        if let Ok(value) = std::env::var("IMAGE_FORMATS") {
+            config.formats = value.split(',').filter_map(VariantFormat::parse).collect();
+        }
+        config.sizes.sort_by_key(|(_, width)| *width);
+        config
+    }
+}
+
+// Only still images get variants; animated GIFs are stored as uploaded
+pub fn is_processable(content_type: &str) -> bool {
+    matches!(content_type, "image/jpeg" | "image/png" | "image/webp")
+}
+
+// Variants share the original's content hash: `<hash>_<size>.<ext>`
+pub fn variant_key(key: &str, size: &str, extension: &str) -> String {
+    let stem = key.split('.').next().unwrap_or(key);
+    format!("{}_{}.{}", stem, size, extension)
+}
+
+pub fn is_variant_key(key: &str) -> bool {
+    key.contains('_')
+}
+
+pub struct Variant {
+    pub size: String,
+    pub width: u32,
+    pub extension: &'static str,
+    pub content_type: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+fn decode(bytes: &[u8]) -> Result<(DynamicImage, Orientation), String> {
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    let mut decoder = reader.into_decoder().map_err(|e| e.to_string())?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    Ok((image, orientation))
+}
+
+fn encode(image: &DynamicImage, content_type: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let result = match content_type {
+        "image/jpeg" => image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, 85)),
+        "image/png" => image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png),
+        // WebP and AVIF encoders only take 8-bit RGB(A)
+        "image/webp" => DynamicImage::ImageRgba8(image.to_rgba8()).write_to(&mut Cursor::new(&mut bytes), ImageFormat::WebP),
+        "image/avif" => image.to_rgba8().write_with_encoder(AvifEncoder::new_with_speed_quality(&mut bytes, 8, 70)),
+        other => return Err(format!("Cannot encode {}", other)),
+    };
+    result.map(|_| bytes).map_err(|e| e.to_string())
+}
+
+// Drops EXIF (camera, location, ...) from an upload. Rotated photos are re-encoded upright,
+// since removing EXIF also removes the orientation tag; everything else is stripped losslessly.
+pub fn strip_metadata(bytes: Vec<u8>, content_type: &str) -> Result<Vec<u8>, String> {
+    let (mut image, orientation) = decode(&bytes)?;
+    if orientation != Orientation::NoTransforms {
+        image.apply_orientation(orientation);
+        return encode(&image, content_type);
+    }
+
+    match DynImage::from_bytes(bytes.into()).map_err(|e| e.to_string())? {
+        Some(mut parts) => {
+            parts.set_exif(None);
+            Ok(parts.encoder().bytes().to_vec())
+        }
+        None => Err("Unrecognized image container".to_string()),
+    }
+}
+
+// Renders every configured size in the original's format plus each extra format.
+// Images are never upscaled, so small originals produce variants at their own width.
+pub fn render_variants(bytes: &[u8], content_type: &'static str, config: &ImageConfig) -> Result<Vec<Variant>, String> {
+    let (mut image, orientation) = decode(bytes)?;
+    image.apply_orientation(orientation);
+    let original_extension = match content_type {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        _ => "webp",
+    };
+
+    let mut variants = vec![];
+    for (size, max_width) in &config.sizes {
+        let resized = if image.width() > *max_width {
+            image.resize(*max_width, u32::MAX, FilterType::CatmullRom)
+        } else {
+            image.clone()
+        };
+
+        let mut targets = vec![(original_extension, content_type)];
+        for format in &config.formats {
+            if format.content_type() != content_type {
+                targets.push((format.extension(), format.content_type()));
+            }
+        }
+        for (extension, target_type) in targets {
+            variants.push(Variant {
+                size: size.clone(),
+                width: resized.width(),
+                extension,
+                content_type: target_type,
+                bytes: encode(&resized, target_type)?,
+            });
+        }
+    }
+    Ok(variants)
+}
+
+// Picks the smallest pre-rendered size at least `width` wide (the largest when none is),
+// in the requested format or the original's
+pub fn resolve_variant(
+    key: &str,
+    width: Option<u32>,
+    format: Option<&str>,
+    config: &ImageConfig,
+) -> Result<Option<String>, String> {
+    let Some(original_extension) = key.rsplit_once('.').map(|(_, extension)| extension) else {
+        return Ok(None);
+    };
+    if !matches!(original_extension, "jpg" | "png" | "webp") || is_variant_key(key) {
+        return Ok(None);
+    }
+
+    let extension = match format {
+        None | Some("original") => original_extension,
+        Some(format) => match VariantFormat::parse(format) {
+            Some(format) if config.formats.contains(&format) || format.extension() == original_extension => {
+                format.extension()
+            }
+            _ => return Err(format!("Unsupported image format '{}'", format)),
+        },
+    };
+
+    let size = match width {
+        Some(width) => config
+            .sizes
+            .iter()
+            .find(|(_, max_width)| *max_width >= width)
+            .or(config.sizes.last()),
+        None => config.sizes.last(),
+    };
+    Ok(size.map(|(size, _)| variant_key(key, size, extension)))
+}
@@ -1,179 +1,86 @@
 use actix_cors::Cors;
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use std::sync::Arc;
-use rand::Rng;
-use models::{Product, CreateProductRequest, ProductQuery};
-use actix_web::ResponseError;
-use actix_multipart::Multipart;
-use futures::StreamExt;
-use std::path::Path;
-use std::fs;
-use std::io::Write;
-use actix_web::web::Bytes;
-use std::path::PathBuf;
-use tokio::time::{sleep, Duration};
-use tokio::sync::broadcast;
-use std::sync::atomic::{AtomicBool, Ordering};
-use actix_web_actors::ws;
-use actix::{Actor, StreamHandler, Handler, AsyncContext, Message};
-use actix_web_actors::ws::WebsocketContext;
-use bytestring::ByteString;
-use sqlx::sqlite::SqlitePoolOptions;
+use models::{Product, CreateProductRequest, GenerationMode, ProductEvent, ProductQuery, ToggleRequest};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use std::str::FromStr;
 use dotenv::dotenv;
-use std::env;
-
+use files::UploadConfig;
+use generator::GeneratorController;
+use rbac::{AuthConfig, Permission, Principal, RequirePermission};
+use realtime::EventBus;
+use scanner::Scanner;
+use storage::BlobStore;
+use tracing::Instrument;
+
+mod api_keys;
+mod audit;
+mod auth;
+mod files;
+mod fixtures;
+mod generator;
+mod health;
+mod images;
+mod media;
+mod metrics;
 mod models;
+mod pricing;
+mod rbac;
+mod realtime;
+mod resumable;
+mod scanner;
+mod storage;
+mod telemetry;
 mod validation;
 #[cfg(test)]
 mod tests;
 
-// WebSocket actor
-struct ProductWs {
-    product_rx: broadcast::Receiver<Product>,
-}
-
-// Message wrapper for Product
-struct ProductMessage(Product);
-
-impl Message for ProductMessage {
-    type Result = ();
-}
-
-impl Actor for ProductWs {
-    type Context = WebsocketContext<Self>;
-
-    fn started(&mut self, ctx: &mut Self::Context) {
-        println!("New WebSocket client connected: {:?}", ctx.address());
-        
-        // Start listening for new products
-        let addr = ctx.address();
-        let mut rx = self.product_rx.resubscribe();
-        
-        actix_rt::spawn(async move {
-            while let Ok(product) = rx.recv().await {
-                addr.do_send(ProductMessage(product));
-            }
-        });
-    }
-
-    fn stopping(&mut self, ctx: &mut Self::Context) -> actix::Running {
-        println!("WebSocket client disconnected: {:?}", ctx.address());
-        actix::Running::Stop
-    }
-}
-
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ProductWs {
-    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-        match msg {
-            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
-            Ok(ws::Message::Text(text)) => ctx.text(text),
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
-            _ => (),
-        }
-    }
-}
-
-impl Handler<ProductMessage> for ProductWs {
-    type Result = ();
-
-    fn handle(&mut self, msg: ProductMessage, ctx: &mut Self::Context) {
-        ctx.text(ByteString::from(serde_json::to_string(&msg.0).unwrap()));
-    }
-}
-
 // Global state to store WebSocket channels and database pool
 pub struct AppState {
-    is_generating: Arc<AtomicBool>,
-    product_tx: broadcast::Sender<Product>,
+    generator: GeneratorController,
+    events: EventBus,
     db_pool: sqlx::SqlitePool,
+    auth_config: AuthConfig,
+    uploads: UploadConfig,
+    blobs: Box<dyn BlobStore>,
+    scanner: Box<dyn Scanner>,
+    upload_locks: resumable::SessionLocks,
+    log_levels: telemetry::LogLevels,
+    metrics: metrics::Metrics,
 }
 
-// Function to generate a random product
-fn generate_random_product(id: i64) -> Product {
-    let mut rng = rand::thread_rng();
-    let categories = vec!["Electronics", "Books", "Clothing", "Home", "Toys"];
-    let category = categories[rng.gen_range(0..categories.len())].to_string();
-
-    Product {
-        id,
-        name: format!("Product-{}", id),
-        price: rng.gen_range(10.0..500.0),
-        image: Some(format!("/images/product-{}.jpg", id)),
-        description: Some(format!("This is a description for Product-{}", id)),
-        category: Some(category),
-    }
-}
-
-// Function to generate products periodically
-async fn generate_products_periodically(app_state: web::Data<AppState>) {
-    while app_state.is_generating.load(Ordering::Relaxed) {
-        let new_product = generate_random_product(rand::thread_rng().gen_range(1000..9999));
-        
-        // Insert into database
-        let _ = sqlx::query!(
-            r#"
-            INSERT INTO products (name, price, image, description, category)
-            VALUES (?, ?, ?, ?, ?)
-            "#,
-            new_product.name,
-            new_product.price,
-            new_product.image,
-            new_product.description,
-            new_product.category
-        )
-        .execute(&app_state.db_pool)
-        .await;
-        
-        // Broadcast the new product to all connected WebSocket clients
-        let _ = app_state.product_tx.send(new_product);
-        
-        println!("Generated a new product.");
-        sleep(Duration::from_secs(3)).await;
-    }
-    println!("Stopped generating products.");
-}
-
-// WebSocket handler
-async fn product_ws(
-    req: actix_web::HttpRequest,
-    stream: web::Payload,
-    app_state: web::Data<AppState>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let product_rx = app_state.product_tx.subscribe();
-    let ws = ProductWs { product_rx };
-    
-    // Send initial products from database
-    let products = sqlx::query_as!(
-        Product,
-        r#"SELECT id, name, price, image, description, category FROM products"#
-    )
-    .fetch_all(&app_state.db_pool)
-    .await
-    .unwrap_or_else(|_| vec![]);
-    
-    let _initial_msg = ws::Message::Text(ByteString::from(serde_json::to_string(&products).unwrap()));
-    
-    let resp = ws::start(ws, &req, stream)?;
-    Ok(resp)
+// Current time as seconds since the Unix epoch, the format all timestamp columns use
+pub fn now_unix() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
 }
 
 // API endpoint to toggle product generation
 async fn toggle_generation(
     app_state: web::Data<AppState>,
-    state: web::Json<bool>,
+    principal: Principal,
+    request: web::Json<ToggleRequest>,
 ) -> impl Responder {
-    app_state.is_generating.store(*state, Ordering::Relaxed);
+    let (enabled, config) = request.into_inner().into_parts();
+    if let Err(e) = validation::validate_generator_config(&config) {
+        return e.error_response();
+    }
 
-    if *state {
-        let app_state_clone = app_state.clone();
-        tokio::spawn(async move {
-            generate_products_periodically(app_state_clone).await;
-        });
+    let mode = config.mode;
+    if enabled {
+        app_state.generator.start(app_state.clone(), config).await;
+    } else {
+        app_state.generator.stop().await;
     }
+    tracing::info!(?mode, enabled, actor = %principal.name, "generator toggled");
 
+    let status = app_state.generator.status().await;
+    let active_mode = status.config.as_ref().filter(|_| status.running).map(|c| c.mode);
     HttpResponse::Ok().json(serde_json::json!({
         "status": "success",
-        "is_generating": *state
+        "is_generating": active_mode == Some(GenerationMode::Generate),
+        "is_simulating": active_mode == Some(GenerationMode::PriceDrift)
     }))
 }
 
@@ -182,6 +89,7 @@ async fn get_products(data: web::Data<AppState>, query: web::Query<ProductQuery>
         r#"SELECT id, name, price, image, description, category FROM products"#
     )
     .fetch_all(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "products"))
     .await
     .unwrap_or_else(|_| vec![])
     .into_iter()
@@ -219,6 +127,7 @@ async fn get_product(data: web::Data<AppState>, id: web::Path<i32>) -> impl Resp
         product_id
     )
     .fetch_optional(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "products"))
     .await
     {
         Ok(Some(row)) => {
@@ -241,8 +150,23 @@ async fn get_product(data: web::Data<AppState>, id: web::Path<i32>) -> impl Resp
     }
 }
 
+// Loads a single product, used to capture the previous state of a row before it is changed
+async fn find_product(db_pool: &sqlx::SqlitePool, product_id: i32) -> Option<Product> {
+    sqlx::query_as!(
+        Product,
+        r#"SELECT id, name, price, image, description, category FROM products WHERE id = ?"#,
+        product_id
+    )
+    .fetch_optional(db_pool)
+    .instrument(telemetry::db_span("SELECT", "products"))
+    .await
+    .ok()
+    .flatten()
+}
+
 async fn create_product(
     data: web::Data<AppState>,
+    principal: Principal,
     product: web::Json<CreateProductRequest>,
 ) -> impl Responder {
     if let Err(e) = validation::validate_product(&product) {
@@ -261,6 +185,7 @@ async fn create_product(
         product.category
     )
     .execute(&data.db_pool)
+    .instrument(telemetry::db_span("INSERT", "products"))
     .await
     {
         Ok(result) => {
@@ -272,6 +197,18 @@ async fn create_product(
                 description: product.description.clone(),
                 category: Some(product.category.clone()),
             };
+            audit::record(
+                &data.db_pool,
+                &principal.name,
+                "create",
+                "product",
+                &new_product.id.to_string(),
+                None,
+                serde_json::to_value(&new_product).ok().as_ref(),
+            )
+            .await;
+            pricing::record_price(&data, &new_product, None, "create").await;
+            data.events.publish(ProductEvent::ProductCreated(new_product.clone()));
             HttpResponse::Created().json(new_product)
         },
         Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
@@ -282,11 +219,13 @@ async fn create_product(
 
 async fn update_product(
     data: web::Data<AppState>,
+    principal: Principal,
     id: web::Path<i32>,
     product: web::Json<Product>,
 ) -> impl Responder {
     let product_id = id.into_inner();
-    
+    let before = find_product(&data.db_pool, product_id).await;
+
     match sqlx::query!(
         r#"
         UPDATE products
@@ -301,11 +240,28 @@ async fn update_product(
         product_id
     )
     .execute(&data.db_pool)
+    .instrument(telemetry::db_span("UPDATE", "products"))
     .await
     {
         Ok(result) => {
             if result.rows_affected() > 0 {
-                HttpResponse::Ok().json(product.into_inner())
+                let updated = Product { id: product_id as i64, ..product.into_inner() };
+                let old_price = before.as_ref().map(|p| p.price);
+                audit::record(
+                    &data.db_pool,
+                    &principal.name,
+                    "update",
+                    "product",
+                    &product_id.to_string(),
+                    before.and_then(|p| serde_json::to_value(p).ok()).as_ref(),
+                    serde_json::to_value(&updated).ok().as_ref(),
+                )
+                .await;
+                if old_price != Some(updated.price) {
+                    pricing::record_price(&data, &updated, old_price, "update").await;
+                }
+                data.events.publish(ProductEvent::ProductUpdated(updated.clone()));
+                HttpResponse::Ok().json(updated)
             } else {
                 HttpResponse::NotFound().json(serde_json::json!({
                     "error": "Product not found"
@@ -318,18 +274,37 @@ async fn update_product(
     }
 }
 
-async fn delete_product(data: web::Data<AppState>, id: web::Path<i32>) -> impl Responder {
+async fn delete_product(
+    data: web::Data<AppState>,
+    principal: Principal,
+    id: web::Path<i32>,
+) -> impl Responder {
     let product_id = id.into_inner();
-    
+    let before = find_product(&data.db_pool, product_id).await;
+
     match sqlx::query!(
         r#"DELETE FROM products WHERE id = ?"#,
         product_id
     )
     .execute(&data.db_pool)
+    .instrument(telemetry::db_span("DELETE", "products"))
     .await
     {
         Ok(result) => {
             if result.rows_affected() > 0 {
+                audit::record(
+                    &data.db_pool,
+                    &principal.name,
+                    "delete",
+                    "product",
+                    &product_id.to_string(),
+                    before.as_ref().and_then(|p| serde_json::to_value(p).ok()).as_ref(),
+                    None,
+                )
+                .await;
+                if let Some(deleted) = before {
+                    data.events.publish(ProductEvent::ProductDeleted(deleted));
+                }
                 HttpResponse::NoContent().finish()
             } else {
                 HttpResponse::NotFound().json(serde_json::json!({
@@ -343,52 +318,45 @@ async fn delete_product(data: web::Data<AppState>, id: web::Path<i32>) -> impl R
     }
 }
 
-fn simulate_price_change(price: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    let change_percentage = rng.gen_range(-5.0..=5.0); // Random change between -5% and +5%
-    price * (1.0 + change_percentage / 100.0)
-}
-
+#[tracing::instrument(skip_all, fields(products = products.len()))]
 fn filter_and_sort_products(products: &[Product], query: &ProductQuery) -> Vec<Product> {
     let mut filtered = products.to_vec();
 
     // Apply filters
     if let Some(category) = &query.category {
-        filtered.retain(|p| p.category.as_ref().map_or(false, |c| c == category));
+        filtered.retain(|p| p.category.as_ref().is_some_and(|c| c == category));
     }
 
     if let Some(min_price) = query.min_price {
-        println!("Filtering by min_price: {}", min_price);
         let before_count = filtered.len();
         filtered.retain(|p| p.price >= min_price);
-        println!("Products after min_price filter: {} -> {}", before_count, filtered.len());
+        tracing::debug!(min_price, before = before_count, after = filtered.len(), "filtered by min_price");
     }
 
     if let Some(max_price) = query.max_price {
-        println!("Filtering by max_price: {}", max_price);
         let before_count = filtered.len();
         filtered.retain(|p| p.price <= max_price);
-        println!("Products after max_price filter: {} -> {}", before_count, filtered.len());
+        tracing::debug!(max_price, before = before_count, after = filtered.len(), "filtered by max_price");
     }
 
     if let Some(search_term) = &query.search_term {
         let search_term = search_term.to_lowercase();
         filtered.retain(|p| {
             p.name.to_lowercase().contains(&search_term) ||
-            p.description.as_ref().map_or(false, |d| d.to_lowercase().contains(&search_term)) ||
-            p.category.as_ref().map_or(false, |c| c.to_lowercase().contains(&search_term))
+            p.description.as_ref().is_some_and(|d| d.to_lowercase().contains(&search_term)) ||
+            p.category.as_ref().is_some_and(|c| c.to_lowercase().contains(&search_term))
         });
     }
 
     // Apply sorting
     if let Some(sort_by) = &query.sort_by {
-        println!("Sorting by: {}, order: {:?}", sort_by, query.sort_order);
+        tracing::debug!(sort_by = %sort_by, sort_order = ?query.sort_order, "sorting products");
         let sort_order = query.sort_order.as_deref().unwrap_or("asc");
         filtered.sort_by(|a, b| {
             let comparison = match sort_by.as_str() {
                 "name" => a.name.cmp(&b.name),
                 "price" => a.price.partial_cmp(&b.price).unwrap_or(std::cmp::Ordering::Equal),
-                "category" => a.category.as_ref().unwrap_or(&String::new()).cmp(&b.category.as_ref().unwrap_or(&String::new())),
+                "category" => a.category.as_ref().unwrap_or(&String::new()).cmp(b.category.as_ref().unwrap_or(&String::new())),
                 _ => std::cmp::Ordering::Equal,
             };
             if sort_order == "desc" {
@@ -402,201 +370,224 @@ fn filter_and_sort_products(products: &[Product], query: &ProductQuery) -> Vec<P
     filtered
 }
 
-async fn upload_file(mut payload: Multipart) -> impl Responder {
-    // Create uploads directory if it doesn't exist
-    let upload_dir = Path::new("uploads");
-    if !upload_dir.exists() {
-        if let Err(e) = fs::create_dir_all(upload_dir) {
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to create upload directory: {}", e)
-            }));
-        }
-    }
-
-    let mut filename = String::new();
-
-    while let Some(item) = payload.next().await {
-        let mut field = match item {
-            Ok(field) => field,
-            Err(e) => {
-                return HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": format!("Failed to process upload: {}", e)
-                }));
-            }
-        };
-
-        let content_disposition = field.content_disposition();
-        filename = content_disposition.get_filename().unwrap_or("unknown").to_string();
-        let file_path = upload_dir.join(&filename);
-
-        let mut file = match fs::File::create(&file_path) {
-            Ok(file) => file,
-            Err(e) => {
-                return HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("Failed to create file: {}", e)
-                }));
-            }
-        };
-
-        while let Some(chunk) = field.next().await {
-            let data = match chunk {
-                Ok(data) => data,
-                Err(e) => {
-                    return HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": format!("Failed to read chunk: {}", e)
-                    }));
-                }
-            };
-
-            if let Err(e) = file.write_all(&data) {
-                return HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("Failed to write chunk: {}", e)
-                }));
-            }
-        }
-    }
-
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "success",
-        "message": "File uploaded successfully",
-        "filename": filename
-    }))
-}
-
-async fn download_file(filename: web::Path<String>) -> impl Responder {
-    let filepath = Path::new("uploads").join(&*filename);
-    
-    // Check if file exists
-    if !filepath.exists() {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("File '{}' not found", filename)
-        }));
-    }
-
-    // Get file metadata
-    let metadata = match fs::metadata(&filepath) {
-        Ok(m) => m,
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to get file metadata: {}", e)
-            }));
-        }
-    };
-
-    // Determine content type based on file extension
-    let content_type = match filepath.extension().and_then(|ext| ext.to_str()) {
-        Some("pdf") => "application/pdf",
-        Some("jpg") | Some("jpeg") => "image/jpeg",
-        Some("png") => "image/png",
-        Some("txt") => "text/plain",
-        _ => "application/octet-stream",
-    };
-
-    // Create a stream from the file
-    let filepath_clone = filepath.clone();
-    let stream = futures::stream::once(async move { 
-        match fs::read(&filepath_clone) {
-            Ok(data) => Ok::<_, std::io::Error>(Bytes::from(data)),
-            Err(e) => Err(e)
-        }
-    });
-
-    HttpResponse::Ok()
-        .content_type(content_type)
-        .append_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
-        .append_header(("Content-Length", metadata.len()))
-        .streaming(stream)
-}
-
-async fn list_files() -> impl Responder {
-    let upload_dir = Path::new("uploads");
-    
-    // Check if directory exists
-    if !upload_dir.exists() {
-        return HttpResponse::Ok().json(serde_json::json!({
-            "files": Vec::<String>::new()
-        }));
-    }
-
-    // Read directory contents
-    let files = match fs::read_dir(upload_dir) {
-        Ok(entries) => {
-            entries
-                .filter_map(|entry| {
-                    entry.ok().and_then(|e| {
-                        e.file_name().into_string().ok()
-                    })
-                })
-                .collect::<Vec<String>>()
-        },
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to read upload directory: {}", e)
-            }));
-        }
-    };
-
-    HttpResponse::Ok().json(serde_json::json!({
-        "files": files
-    }))
-}
-
-async fn health_check() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "ok"
-    }))
+fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/ws", web::get().to(realtime::product_ws))
+        .route("/api/events", web::get().to(realtime::product_events))
+        .route("/api/auth/register", web::post().to(auth::register))
+        .route("/api/auth/login", web::post().to(auth::login))
+        .route("/api/auth/logout", web::post().to(auth::logout))
+        .route("/api/auth/logout-all", web::post().to(auth::logout_all))
+        .route("/api/auth/me", web::get().to(auth::me))
+        .route(
+            "/api/users/{id}/role",
+            web::put().to(rbac::update_user_role).wrap(RequirePermission(Permission::ManageUsers)),
+        )
+        .service(
+            web::resource("/api/api-keys")
+                .wrap(RequirePermission(Permission::ManageApiKeys))
+                .route(web::get().to(api_keys::list_api_keys))
+                .route(web::post().to(api_keys::create_api_key)),
+        )
+        .route(
+            "/api/api-keys/{id}/rotate",
+            web::post().to(api_keys::rotate_api_key).wrap(RequirePermission(Permission::ManageApiKeys)),
+        )
+        .route(
+            "/api/api-keys/{id}",
+            web::delete().to(api_keys::revoke_api_key).wrap(RequirePermission(Permission::ManageApiKeys)),
+        )
+        .route(
+            "/api/toggle-generation",
+            web::post().to(toggle_generation).wrap(RequirePermission(Permission::GeneratorControl)),
+        )
+        .route(
+            "/api/generator/start",
+            web::post().to(generator::start_generator).wrap(RequirePermission(Permission::GeneratorControl)),
+        )
+        .route(
+            "/api/generator/stop",
+            web::post().to(generator::stop_generator).wrap(RequirePermission(Permission::GeneratorControl)),
+        )
+        .route(
+            "/api/generator/status",
+            web::get().to(generator::generator_status).wrap(RequirePermission(Permission::GeneratorControl)),
+        )
+        .route("/api/products", web::get().to(get_products))
+        .route(
+            "/api/products",
+            web::post().to(create_product).wrap(RequirePermission(Permission::ProductsWrite)),
+        )
+        .route("/api/products/{id}", web::get().to(get_product))
+        .route("/api/products/{id}/price-history", web::get().to(pricing::get_price_history))
+        .route(
+            "/api/products/{id}",
+            web::put().to(update_product).wrap(RequirePermission(Permission::ProductsWrite)),
+        )
+        .route(
+            "/api/products/{id}",
+            web::delete().to(delete_product).wrap(RequirePermission(Permission::ProductsWrite)),
+        )
+        .route("/api/products/{id}/media", web::get().to(media::list_media))
+        .route(
+            "/api/products/{id}/media",
+            web::post().to(media::attach_media).wrap(RequirePermission(Permission::ProductsWrite)),
+        )
+        .route(
+            "/api/products/{id}/media/order",
+            web::put().to(media::reorder_media).wrap(RequirePermission(Permission::ProductsWrite)),
+        )
+        .route(
+            "/api/products/{id}/media/{media_id}",
+            web::patch().to(media::update_media).wrap(RequirePermission(Permission::ProductsWrite)),
+        )
+        .route(
+            "/api/products/{id}/media/{media_id}",
+            web::delete().to(media::detach_media).wrap(RequirePermission(Permission::ProductsWrite)),
+        )
+        .route(
+            "/api/media/cleanup",
+            web::post().to(media::cleanup_orphaned_files).wrap(RequirePermission(Permission::FilesDelete)),
+        )
+        .route(
+            "/api/upload",
+            web::post().to(files::upload_file).wrap(RequirePermission(Permission::FilesWrite)),
+        )
+        .route(
+            "/api/uploads",
+            web::post().to(resumable::create_session).wrap(RequirePermission(Permission::FilesWrite)),
+        )
+        .route(
+            "/api/uploads/{id}",
+            web::get().to(resumable::get_session).wrap(RequirePermission(Permission::FilesWrite)),
+        )
+        .route(
+            "/api/uploads/{id}",
+            web::put().to(resumable::put_chunk).wrap(RequirePermission(Permission::FilesWrite)),
+        )
+        .route(
+            "/api/uploads/{id}",
+            web::delete().to(resumable::abort_session).wrap(RequirePermission(Permission::FilesWrite)),
+        )
+        .route(
+            "/api/uploads/{id}/complete",
+            web::post().to(resumable::complete_session).wrap(RequirePermission(Permission::FilesWrite)),
+        )
+        .route("/api/download/{filename}", web::get().to(files::download_file))
+        .route("/api/download/{filename}/presigned", web::get().to(files::presign_download))
+        .route(
+            "/api/upload/presigned",
+            web::post().to(files::presign_upload).wrap(RequirePermission(Permission::FilesWrite)),
+        )
+        .route(
+            "/api/upload/presigned/{key}/complete",
+            web::post().to(files::complete_presigned_upload).wrap(RequirePermission(Permission::FilesWrite)),
+        )
+        .route(
+            "/api/files",
+            web::get().to(files::list_files).wrap(RequirePermission(Permission::FilesRead)),
+        )
+        .route(
+            "/api/files/{id}",
+            web::delete().to(files::delete_file).wrap(RequirePermission(Permission::FilesDelete)),
+        )
+        .route(
+            "/api/audit",
+            web::get().to(audit::list_audit).wrap(RequirePermission(Permission::ViewAudit)),
+        )
+        .service(
+            web::resource("/api/admin/log-level")
+                .route(web::get().to(telemetry::get_log_level))
+                .route(web::put().to(telemetry::set_log_level))
+                .wrap(RequirePermission(Permission::ManageLogging)),
+        )
+        .route("/api/health", web::get().to(health::live))
+        .route("/health/live", web::get().to(health::live))
+        .route("/health/ready", web::get().to(health::ready))
+        .route("/metrics", web::get().to(metrics::metrics_endpoint));
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Load environment variables
     dotenv().ok();
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some("seed") {
+        return fixtures::run_seed_command(&args[1..])
+            .await
+            .map_err(std::io::Error::other);
+    }
+    let (log_levels, tracer_provider) = telemetry::init();
     
     // Get the current directory and create absolute path for database
     let current_dir = std::env::current_dir().expect("Failed to get current directory");
     let db_path = current_dir.join("products.db");
     let database_url = format!("sqlite:{}", db_path.display());
 
-    // Initialize database connection
+    // Initialize database connection; the file is not tracked, so the first run creates it
+    let options = SqliteConnectOptions::from_str(&database_url)
+        .expect("Invalid database path.")
+        .create_if_missing(true);
     let db_pool = SqlitePoolOptions::new()
         .max_connections(5)
-        .connect(&database_url)
+        .connect_with(options)
         .await
         .expect("Failed to create pool.");
 
-    // Initialize the app state with WebSocket channel and database pool
-    let (product_tx, _) = broadcast::channel(100);
+    sqlx::migrate!()
+        .run(&db_pool)
+        .await
+        .expect("Failed to run migrations.");
+
+    // Initialize the app state with the event bus and database pool
+    let uploads = UploadConfig::from_env();
     let app_state = web::Data::new(AppState {
-        is_generating: Arc::new(AtomicBool::new(false)),
-        product_tx,
+        generator: GeneratorController::default(),
+        events: EventBus::new(100, 1000),
         db_pool,
+        auth_config: AuthConfig::from_env(),
+        blobs: storage::from_env(&uploads.dir),
+        scanner: scanner::from_env(),
+        upload_locks: resumable::SessionLocks::default(),
+        log_levels,
+        metrics: metrics::Metrics::default(),
+        uploads,
     });
 
-    println!("Server running at http://localhost:3001");
+    // Sweeps uploads that never got attached to a product, hourly unless configured otherwise
+    let cleanup_interval = std::env::var("UPLOAD_CLEANUP_INTERVAL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(60 * 60);
+    media::spawn_orphan_cleanup(app_state.clone(), std::time::Duration::from_secs(cleanup_interval));
+    resumable::spawn_session_expiry(app_state.clone(), std::time::Duration::from_secs(10 * 60));
 
+    tracing::info!("Server running at http://localhost:3001");
+
+    let shutdown_state = app_state.clone();
     HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
             .allow_any_method()
             .allow_any_header()
+            .expose_headers([telemetry::REQUEST_ID_HEADER])
             .max_age(3600);
 
         App::new()
             .wrap(cors)
+            .wrap(telemetry::RequestTracing)
             .app_data(app_state.clone())
-            .route("/ws", web::get().to(product_ws))
-            .route("/api/toggle-generation", web::post().to(toggle_generation))
-            .route("/api/products", web::get().to(get_products))
-            .route("/api/products", web::post().to(create_product))
-            .route("/api/products/{id}", web::get().to(get_product))
-            .route("/api/products/{id}", web::put().to(update_product))
-            .route("/api/products/{id}", web::delete().to(delete_product))
-            .route("/api/upload", web::post().to(upload_file))
-            .route("/api/download/{filename}", web::get().to(download_file))
-            .route("/api/files", web::get().to(list_files))
-            .route("/api/health", web::get().to(health_check))
+            .configure(configure_routes)
     })
     .bind("127.0.0.1:3001")?
     .run()
-    .await
+    .await?;
+
+    // Let the generator finish its current batch before exiting
+    shutdown_state.generator.stop().await;
+    if let Some(provider) = tracer_provider {
+        let _ = provider.shutdown();
+    }
+    Ok(())
 }
\ No newline at end of file
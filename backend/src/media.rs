@@ -0,0 +1,501 @@
+use crate::models::{Product, ProductEvent};
+use crate::rbac::Principal;
+use crate::storage::is_valid_key;
+use crate::{audit, files, find_product, images, now_unix, telemetry, validation, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io;
+use std::time::Duration;
+use tracing::Instrument;
+
+// Product images point at the download route of their primary media
+const MEDIA_URL_PREFIX: &str = "/api/download/";
+
+#[derive(Debug, Serialize)]
+pub struct ProductMedia {
+    pub id: i64,
+    pub product_id: i64,
+    pub file_key: String,
+    pub alt_text: Option<String>,
+    pub position: i64,
+    pub is_primary: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttachMediaRequest {
+    pub file_key: String,
+    pub alt_text: Option<String>,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateMediaRequest {
+    pub alt_text: Option<String>,
+    pub primary: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReorderMediaRequest {
+    pub media_ids: Vec<i64>,
+}
+
+fn failed(action: &str) -> HttpResponse {
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "error": format!("Failed to {}", action)
+    }))
+}
+
+fn product_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "Product not found"
+    }))
+}
+
+fn media_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "Media not found"
+    }))
+}
+
+// Empty alt text is stored as none
+fn clean_alt_text(alt_text: Option<&str>) -> Option<String> {
+    alt_text.map(str::trim).filter(|text| !text.is_empty()).map(str::to_string)
+}
+
+async fn load_media(db_pool: &sqlx::SqlitePool, product_id: i32) -> Result<Vec<ProductMedia>, sqlx::Error> {
+    sqlx::query_as!(
+        ProductMedia,
+        r#"
+        SELECT id AS "id!", product_id, file_key, alt_text, position, is_primary AS "is_primary: bool"
+        FROM product_media
+        WHERE product_id = ?
+        ORDER BY position, id
+        "#,
+        product_id
+    )
+    .fetch_all(db_pool)
+    .instrument(telemetry::db_span("SELECT", "product_media"))
+    .await
+}
+
+// Numbers the gallery 0, 1, 2, ... in the given order
+async fn set_positions(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    media_ids: &[i64],
+) -> Result<(), sqlx::Error> {
+    for (position, media_id) in media_ids.iter().enumerate() {
+        let position = position as i64;
+        sqlx::query!("UPDATE product_media SET position = ? WHERE id = ?", position, media_id)
+            .execute(&mut **transaction)
+            .instrument(telemetry::db_span("UPDATE", "product_media"))
+            .await?;
+    }
+    Ok(())
+}
+
+// Keeps `Product.image` pointing at the primary media, and tells subscribers when it moves
+async fn sync_product_image(data: &AppState, product_id: i32) {
+    let Some(before) = find_product(&data.db_pool, product_id).await else {
+        return;
+    };
+    let primary = sqlx::query_scalar!(
+        "SELECT file_key FROM product_media WHERE product_id = ? AND is_primary",
+        product_id
+    )
+    .fetch_optional(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "product_media"))
+    .await
+    .ok()
+    .flatten();
+
+    // An image set by hand stays until media replaces it
+    let image = match primary {
+        Some(key) => Some(format!("{}{}", MEDIA_URL_PREFIX, key)),
+        None if before.image.as_deref().is_some_and(|image| image.starts_with(MEDIA_URL_PREFIX)) => None,
+        None => before.image.clone(),
+    };
+    if image == before.image {
+        return;
+    }
+
+    if let Err(e) = sqlx::query!("UPDATE products SET image = ? WHERE id = ?", image, product_id)
+        .execute(&data.db_pool)
+        .instrument(telemetry::db_span("UPDATE", "products"))
+        .await
+    {
+        tracing::error!(product_id, error = %e, "Failed to update product image");
+        return;
+    }
+    data.events.publish(ProductEvent::ProductUpdated(Product { image, ..before }));
+}
+
+// Records the gallery before and after a change, then refreshes the product image
+async fn finish_change(
+    data: &AppState,
+    principal: &Principal,
+    action: &str,
+    product_id: i32,
+    before: &[ProductMedia],
+) -> Vec<ProductMedia> {
+    let after = load_media(&data.db_pool, product_id).await.unwrap_or_default();
+    audit::record(
+        &data.db_pool,
+        &principal.name,
+        action,
+        "product_media",
+        &product_id.to_string(),
+        serde_json::to_value(before).ok().as_ref(),
+        serde_json::to_value(&after).ok().as_ref(),
+    )
+    .await;
+    sync_product_image(data, product_id).await;
+    after
+}
+
+pub async fn list_media(data: web::Data<AppState>, id: web::Path<i32>) -> impl Responder {
+    let product_id = id.into_inner();
+    if find_product(&data.db_pool, product_id).await.is_none() {
+        return product_not_found();
+    }
+    match load_media(&data.db_pool, product_id).await {
+        Ok(media) => HttpResponse::Ok().json(media),
+        Err(_) => failed("load media"),
+    }
+}
+
+// Links an uploaded image to the product; the first media attached becomes the primary image
+pub async fn attach_media(
+    data: web::Data<AppState>,
+    principal: Principal,
+    id: web::Path<i32>,
+    request: web::Json<AttachMediaRequest>,
+) -> impl Responder {
+    let product_id = id.into_inner();
+    if let Err(e) = validation::validate_alt_text(request.alt_text.as_deref()) {
+        return e.error_response();
+    }
+    if find_product(&data.db_pool, product_id).await.is_none() {
+        return product_not_found();
+    }
+
+    let file_key = request.file_key.trim();
+    if !is_valid_key(file_key) || images::is_variant_key(file_key) || files::is_pending_key(file_key) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Media must be an uploaded file's key"
+        }));
+    }
+    let content_type = sqlx::query_scalar!("SELECT content_type FROM files WHERE key = ? LIMIT 1", file_key)
+        .fetch_optional(&data.db_pool)
+        .instrument(telemetry::db_span("SELECT", "files"))
+        .await;
+    match content_type {
+        Ok(Some(content_type)) if content_type.starts_with("image/") => {}
+        Ok(Some(_)) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("File '{}' is not an image", file_key)
+            }));
+        }
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": format!("File '{}' not found", file_key)
+            }));
+        }
+        Err(_) => return failed("look up file"),
+    }
+
+    let Ok(before) = load_media(&data.db_pool, product_id).await else {
+        return failed("load media");
+    };
+    let alt_text = clean_alt_text(request.alt_text.as_deref());
+    let position = before.len() as i64;
+    let is_primary = request.primary || before.iter().all(|media| !media.is_primary);
+    let created_at = now_unix();
+
+    let attached = async {
+        let mut transaction = data.db_pool.begin().await?;
+        if is_primary {
+            sqlx::query!("UPDATE product_media SET is_primary = 0 WHERE product_id = ?", product_id)
+                .execute(&mut *transaction)
+                .instrument(telemetry::db_span("UPDATE", "product_media"))
+                .await?;
+        }
+        let media_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO product_media (product_id, file_key, alt_text, position, is_primary, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING id AS "id!"
+            "#,
+            product_id,
+            file_key,
+            alt_text,
+            position,
+            is_primary,
+            created_at
+        )
+        .fetch_one(&mut *transaction)
+        .instrument(telemetry::db_span("INSERT", "product_media"))
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(media_id)
+    }
+    .await;
+
+    let media_id = match attached {
+        Ok(media_id) => media_id,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!("File '{}' is already attached to this product", file_key)
+            }));
+        }
+        Err(_) => return failed("attach media"),
+    };
+
+    let after = finish_change(&data, &principal, "attach", product_id, &before).await;
+    match after.into_iter().find(|media| media.id == media_id) {
+        Some(media) => HttpResponse::Created().json(media),
+        None => failed("attach media"),
+    }
+}
+
+// Changes alt text and the primary flag; clearing the primary leaves the product without an image
+pub async fn update_media(
+    data: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<(i32, i64)>,
+    request: web::Json<UpdateMediaRequest>,
+) -> impl Responder {
+    let (product_id, media_id) = path.into_inner();
+    if let Err(e) = validation::validate_alt_text(request.alt_text.as_deref()) {
+        return e.error_response();
+    }
+    let Ok(before) = load_media(&data.db_pool, product_id).await else {
+        return failed("load media");
+    };
+    let Some(current) = before.iter().find(|media| media.id == media_id) else {
+        return media_not_found();
+    };
+
+    let alt_text = match &request.alt_text {
+        Some(alt_text) => clean_alt_text(Some(alt_text)),
+        None => current.alt_text.clone(),
+    };
+    let is_primary = request.primary.unwrap_or(current.is_primary);
+
+    let updated = async {
+        let mut transaction = data.db_pool.begin().await?;
+        if is_primary {
+            sqlx::query!("UPDATE product_media SET is_primary = 0 WHERE product_id = ?", product_id)
+                .execute(&mut *transaction)
+                .instrument(telemetry::db_span("UPDATE", "product_media"))
+                .await?;
+        }
+        sqlx::query!(
+            "UPDATE product_media SET alt_text = ?, is_primary = ? WHERE id = ?",
+            alt_text,
+            is_primary,
+            media_id
+        )
+        .execute(&mut *transaction)
+        .instrument(telemetry::db_span("UPDATE", "product_media"))
+        .await?;
+        transaction.commit().await
+    }
+    .await;
+    if updated.is_err() {
+        return failed("update media");
+    }
+
+    let after = finish_change(&data, &principal, "update", product_id, &before).await;
+    match after.into_iter().find(|media| media.id == media_id) {
+        Some(media) => HttpResponse::Ok().json(media),
+        None => media_not_found(),
+    }
+}
+
+// Takes the full gallery in its new order, so a stale client cannot drop or duplicate entries
+pub async fn reorder_media(
+    data: web::Data<AppState>,
+    principal: Principal,
+    id: web::Path<i32>,
+    request: web::Json<ReorderMediaRequest>,
+) -> impl Responder {
+    let product_id = id.into_inner();
+    if find_product(&data.db_pool, product_id).await.is_none() {
+        return product_not_found();
+    }
+    let Ok(before) = load_media(&data.db_pool, product_id).await else {
+        return failed("load media");
+    };
+
+    let current: HashSet<i64> = before.iter().map(|media| media.id).collect();
+    let requested: HashSet<i64> = request.media_ids.iter().copied().collect();
+    if requested.len() != request.media_ids.len() || requested != current {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "media_ids must list every media item of the product exactly once"
+        }));
+    }
+
+    let reordered = async {
+        let mut transaction = data.db_pool.begin().await?;
+        set_positions(&mut transaction, &request.media_ids).await?;
+        transaction.commit().await
+    }
+    .await;
+    if reordered.is_err() {
+        return failed("reorder media");
+    }
+
+    HttpResponse::Ok().json(finish_change(&data, &principal, "reorder", product_id, &before).await)
+}
+
+// Unlinks media from the product; the file itself stays until the orphan cleanup removes it
+pub async fn detach_media(
+    data: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<(i32, i64)>,
+) -> impl Responder {
+    let (product_id, media_id) = path.into_inner();
+    let Ok(before) = load_media(&data.db_pool, product_id).await else {
+        return failed("load media");
+    };
+    let Some(detached) = before.iter().find(|media| media.id == media_id) else {
+        return media_not_found();
+    };
+
+    // The next image in line takes over as primary
+    let remaining: Vec<i64> = before.iter().map(|media| media.id).filter(|id| *id != media_id).collect();
+    let promoted = remaining.first().filter(|_| detached.is_primary);
+
+    let removed = async {
+        let mut transaction = data.db_pool.begin().await?;
+        sqlx::query!("DELETE FROM product_media WHERE id = ?", media_id)
+            .execute(&mut *transaction)
+            .instrument(telemetry::db_span("DELETE", "product_media"))
+            .await?;
+        set_positions(&mut transaction, &remaining).await?;
+        if let Some(promoted) = promoted {
+            sqlx::query!("UPDATE product_media SET is_primary = 1 WHERE id = ?", promoted)
+                .execute(&mut *transaction)
+                .instrument(telemetry::db_span("UPDATE", "product_media"))
+                .await?;
+        }
+        transaction.commit().await
+    }
+    .await;
+    if removed.is_err() {
+        return failed("detach media");
+    }
+
+    finish_change(&data, &principal, "detach", product_id, &before).await;
+    HttpResponse::NoContent().finish()
+}
+
+// The stored file a product image URL points at, if it points at one
+fn image_key(image: &str) -> Option<&str> {
+    let key = image.strip_prefix(MEDIA_URL_PREFIX)?;
+    key.split('?').next().filter(|key| !key.is_empty())
+}
+
+// Products whose `image` was set by hand to the file or one of its variants, which media never touches
+pub async fn products_showing(db_pool: &sqlx::SqlitePool, key: &str) -> Result<Vec<i64>, sqlx::Error> {
+    let stem = content_stem(key);
+    let prefix = format!("{}{}", MEDIA_URL_PREFIX, stem);
+    let images = sqlx::query!(
+        r#"SELECT id AS "id!", image AS "image!" FROM products WHERE substr(image, 1, length(?1)) = ?1 ORDER BY id"#,
+        prefix
+    )
+    .fetch_all(db_pool)
+    .instrument(telemetry::db_span("SELECT", "products"))
+    .await?;
+    Ok(images
+        .into_iter()
+        .filter(|product| image_key(&product.image).map(content_stem) == Some(stem))
+        .map(|product| product.id)
+        .collect())
+}
+
+// Deletes recorded files no product references, along with their variants. Files younger than
+// `grace` are kept, since uploading and attaching are separate requests. Blobs without a `files`
+// row predate that table and are never swept, as nothing tells whether they are still in use;
+// presigned uploads nobody completed within `grace` are the exception.
+pub async fn remove_orphaned_files(data: &AppState, actor: &str, grace: Duration) -> io::Result<Vec<String>> {
+    let mut referenced: HashSet<String> = sqlx::query_scalar!("SELECT DISTINCT file_key FROM product_media")
+        .fetch_all(&data.db_pool)
+        .instrument(telemetry::db_span("SELECT", "product_media"))
+        .await
+        .map_err(io::Error::other)?
+        .iter()
+        .map(|key| content_stem(key).to_string())
+        .collect();
+    let images = sqlx::query_scalar!(
+        r#"SELECT image AS "image!" FROM products WHERE substr(image, 1, length(?1)) = ?1"#,
+        MEDIA_URL_PREFIX
+    )
+    .fetch_all(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "products"))
+    .await
+    .map_err(io::Error::other)?;
+    referenced.extend(images.iter().filter_map(|image| image_key(image)).map(|key| content_stem(key).to_string()));
+    let recorded: HashSet<String> = sqlx::query_scalar!("SELECT key FROM files")
+        .fetch_all(&data.db_pool)
+        .instrument(telemetry::db_span("SELECT", "files"))
+        .await
+        .map_err(io::Error::other)?
+        .iter()
+        .map(|key| content_stem(key).to_string())
+        .collect();
+
+    let cutoff = now_unix() - grace.as_secs() as i64;
+    let mut removed = vec![];
+    for file in data.blobs.list().await? {
+        let stem = content_stem(&file.key);
+        let sweepable = recorded.contains(stem) || files::is_pending_key(&file.key);
+        if !sweepable || referenced.contains(stem) || file.modified_at > cutoff {
+            continue;
+        }
+        data.blobs.delete(&file.key).await?;
+        sqlx::query!("DELETE FROM files WHERE key = ?", file.key)
+            .execute(&data.db_pool)
+            .instrument(telemetry::db_span("DELETE", "files"))
+            .await
+            .map_err(io::Error::other)?;
+        audit::record(&data.db_pool, actor, "delete", "file", &file.key, None, None).await;
+        removed.push(file.key);
+    }
+    Ok(removed)
+}
+
+// Originals and their variants share everything before the first `_` or `.`
+fn content_stem(key: &str) -> &str {
+    key.split(['_', '.']).next().unwrap_or(key)
+}
+
+pub async fn cleanup_orphaned_files(data: web::Data<AppState>, principal: Principal) -> impl Responder {
+    match remove_orphaned_files(&data, &principal.name, data.uploads.orphan_grace).await {
+        Ok(removed) => HttpResponse::Ok().json(serde_json::json!({
+            "removed": removed
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to clean up files: {}", e)
+        })),
+    }
+}
+
+// Runs the orphan cleanup every `interval` for as long as the server is up
+pub fn spawn_orphan_cleanup(data: web::Data<AppState>, interval: Duration) {
+    actix_rt::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately; startup should not wait on a full scan
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match remove_orphaned_files(&data, audit::CLEANUP_ACTOR, data.uploads.orphan_grace).await {
+                Ok(removed) if !removed.is_empty() => tracing::info!(removed = removed.len(), "Removed orphaned files"),
+                Ok(_) => {}
+                Err(e) => tracing::error!(error = %e, "Orphaned file cleanup failed"),
+            }
+        }
+    });
+}
@@ -0,0 +1,128 @@
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::time::Duration;
+
+// Label used for requests that matched no route, so scanners probing random paths cannot create series
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// Prometheus collectors for the whole app; gauges read from other state are refreshed on scrape
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    db_connections: IntGaugeVec,
+    db_max_connections: IntGauge,
+    broadcast_subscribers: IntGauge,
+    broadcast_queued: IntGauge,
+    pub websocket_connections: IntGauge,
+    pub broadcast_lagged: IntCounterVec,
+    pub generator_products: IntCounterVec,
+    pub upload_bytes: IntCounter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled, by method, route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time spent handling HTTP requests"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let db_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Open database connections, by whether they are idle or in use"),
+            &["state"],
+        )
+        .unwrap();
+        let db_max_connections =
+            IntGauge::new("db_pool_max_connections", "Largest number of connections the pool may open").unwrap();
+        let broadcast_subscribers =
+            IntGauge::new("broadcast_subscribers", "Receivers subscribed to catalog events").unwrap();
+        let broadcast_queued =
+            IntGauge::new("broadcast_queued_events", "Catalog events not yet received by every subscriber").unwrap();
+        let websocket_connections =
+            IntGauge::new("websocket_connections", "WebSocket clients currently connected").unwrap();
+        let broadcast_lagged = IntCounterVec::new(
+            Opts::new("broadcast_lagged_events_total", "Catalog events dropped because a subscriber fell behind"),
+            &["transport"],
+        )
+        .unwrap();
+        let generator_products = IntCounterVec::new(
+            Opts::new("generator_products_total", "Products created or repriced by the generator"),
+            &["mode"],
+        )
+        .unwrap();
+        let upload_bytes = IntCounter::new("upload_bytes_total", "Bytes of uploaded files accepted into storage").unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+        registry.register(Box::new(db_connections.clone())).unwrap();
+        registry.register(Box::new(db_max_connections.clone())).unwrap();
+        registry.register(Box::new(broadcast_subscribers.clone())).unwrap();
+        registry.register(Box::new(broadcast_queued.clone())).unwrap();
+        registry.register(Box::new(websocket_connections.clone())).unwrap();
+        registry.register(Box::new(broadcast_lagged.clone())).unwrap();
+        registry.register(Box::new(generator_products.clone())).unwrap();
+        registry.register(Box::new(upload_bytes.clone())).unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_duration,
+            db_connections,
+            db_max_connections,
+            broadcast_subscribers,
+            broadcast_queued,
+            websocket_connections,
+            broadcast_lagged,
+            generator_products,
+            upload_bytes,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn observe_request(&self, method: &str, route: Option<&str>, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        let labels = [method, route.unwrap_or(UNMATCHED_ROUTE), status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_duration.with_label_values(&labels).observe(elapsed.as_secs_f64());
+    }
+
+    fn refresh(&self, data: &AppState) {
+        let open = data.db_pool.size() as i64;
+        let idle = data.db_pool.num_idle() as i64;
+        self.db_connections.with_label_values(&["idle"]).set(idle);
+        self.db_connections.with_label_values(&["in_use"]).set(open - idle);
+        self.db_max_connections.set(data.db_pool.options().get_max_connections() as i64);
+        self.broadcast_subscribers.set(data.events.subscriber_count() as i64);
+        self.broadcast_queued.set(data.events.queued() as i64);
+    }
+
+    pub fn render(&self) -> Result<String, String> {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| e.to_string())?;
+        String::from_utf8(buffer).map_err(|e| e.to_string())
+    }
+}
+
+// Prometheus text exposition; meant to be scraped from inside the network, like the health check
+pub async fn metrics_endpoint(data: web::Data<AppState>) -> impl Responder {
+    data.metrics.refresh(&data);
+    match data.metrics.render() {
+        Ok(body) => HttpResponse::Ok().content_type(TextEncoder::new().format_type()).body(body),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to encode metrics: {}", e)
+        })),
+    }
+}
@@ -1,4 +1,6 @@
+use crate::rbac::Role;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Product {
@@ -29,4 +31,171 @@ pub struct ProductQuery {
     pub sort_order: Option<String>,
     pub offset: Option<usize>,
     pub limit: Option<usize>,
-} 
\ No newline at end of file
+} 
+#[derive(Debug, Serialize, Clone)]
+pub struct User {
+    pub id: i64,
+    pub email: String,
+    pub role: Role,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisterRequest {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriceChange {
+    pub product_id: i64,
+    pub old_price: Option<f64>,
+    pub new_price: f64,
+    pub source: String,
+    pub changed_at: i64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PriceChangedEvent {
+    pub product_id: i64,
+    pub category: Option<String>,
+    pub old_price: Option<f64>,
+    pub new_price: f64,
+    pub source: String,
+    pub changed_at: i64,
+}
+
+// Events broadcast to live subscribers whenever the catalog changes
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum ProductEvent {
+    ProductCreated(Product),
+    ProductUpdated(Product),
+    ProductDeleted(Product),
+    PriceChanged(PriceChangedEvent),
+}
+
+impl ProductEvent {
+    // The `type` tag the event is serialized with
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProductEvent::ProductCreated(_) => "product_created",
+            ProductEvent::ProductUpdated(_) => "product_updated",
+            ProductEvent::ProductDeleted(_) => "product_deleted",
+            ProductEvent::PriceChanged(_) => "price_changed",
+        }
+    }
+
+    pub fn product_id(&self) -> i64 {
+        match self {
+            ProductEvent::ProductCreated(product)
+            | ProductEvent::ProductUpdated(product)
+            | ProductEvent::ProductDeleted(product) => product.id,
+            ProductEvent::PriceChanged(change) => change.product_id,
+        }
+    }
+
+    pub fn category(&self) -> Option<&str> {
+        match self {
+            ProductEvent::ProductCreated(product)
+            | ProductEvent::ProductUpdated(product)
+            | ProductEvent::ProductDeleted(product) => product.category.as_deref(),
+            ProductEvent::PriceChanged(change) => change.category.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GenerationMode {
+    #[default]
+    Generate,
+    PriceDrift,
+}
+
+impl GenerationMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GenerationMode::Generate => "generate",
+            GenerationMode::PriceDrift => "price_drift",
+        }
+    }
+}
+
+fn default_interval_ms() -> u64 {
+    3000
+}
+
+fn default_batch_size() -> u32 {
+    1
+}
+
+fn default_volatility() -> f64 {
+    5.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneratorConfig {
+    #[serde(default)]
+    pub mode: GenerationMode,
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: u32,
+    // Only products in this category are repriced in price-drift mode
+    pub category: Option<String>,
+    // Relative weights of the categories new products are drawn from
+    pub category_mix: Option<HashMap<String, u32>>,
+    // Largest relative price move per tick, in percent
+    #[serde(default = "default_volatility")]
+    pub volatility: f64,
+    // The generator stops by itself after this many items
+    pub max_count: Option<u64>,
+    // Makes the generated products reproducible
+    pub seed: Option<u64>,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            mode: GenerationMode::Generate,
+            interval_ms: default_interval_ms(),
+            batch_size: default_batch_size(),
+            category: None,
+            category_mix: None,
+            volatility: default_volatility(),
+            max_count: None,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ToggleConfig {
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub generator: GeneratorConfig,
+}
+
+// The toggle endpoint still accepts a bare `true`/`false` for plain product generation
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ToggleRequest {
+    Enabled(bool),
+    Config(ToggleConfig),
+}
+
+impl ToggleRequest {
+    pub fn into_parts(self) -> (bool, GeneratorConfig) {
+        match self {
+            ToggleRequest::Enabled(enabled) => (enabled, GeneratorConfig::default()),
+            ToggleRequest::Config(config) => (config.enabled, config.generator),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::models::{PriceChange, PriceChangedEvent, Product, ProductEvent};
+use crate::{now_unix, telemetry, AppState};
+use actix_web::{web, HttpResponse, Responder};
+use serde::Deserialize;
+use tracing::Instrument;
+
+#[derive(Debug, Deserialize)]
+pub struct PriceHistoryQuery {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+// Appends a point to the product's price series and tells live subscribers about actual changes
+pub async fn record_price(app_state: &AppState, product: &Product, old_price: Option<f64>, source: &str) {
+    let product_id = product.id;
+    let new_price = product.price;
+    let changed_at = now_unix();
+
+    if let Err(e) = sqlx::query!(
+        r#"
+        INSERT INTO price_history (product_id, old_price, new_price, source, changed_at)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+        product_id,
+        old_price,
+        new_price,
+        source,
+        changed_at
+    )
+    .execute(&app_state.db_pool)
+    .instrument(telemetry::db_span("INSERT", "price_history"))
+    .await
+    {
+        tracing::error!(product_id, error = %e, "Failed to record price");
+        return;
+    }
+
+    if old_price.is_some() {
+        app_state.events.publish(ProductEvent::PriceChanged(PriceChangedEvent {
+            product_id,
+            category: product.category.clone(),
+            old_price,
+            new_price,
+            source: source.to_string(),
+            changed_at,
+        }));
+    }
+}
+
+pub async fn get_price_history(
+    data: web::Data<AppState>,
+    id: web::Path<i64>,
+    query: web::Query<PriceHistoryQuery>,
+) -> impl Responder {
+    let product_id = id.into_inner();
+
+    let points = match sqlx::query_as!(
+        PriceChange,
+        r#"
+        SELECT product_id, old_price, new_price, source, changed_at
+        FROM price_history
+        WHERE product_id = ?1
+          AND (?2 IS NULL OR changed_at >= ?2)
+          AND (?3 IS NULL OR changed_at <= ?3)
+        ORDER BY changed_at, id
+        "#,
+        product_id,
+        query.from,
+        query.to
+    )
+    .fetch_all(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "price_history"))
+    .await
+    {
+        Ok(points) => points,
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to load price history"
+            }))
+        }
+    };
+
+    if points.is_empty() {
+        let exists = sqlx::query_scalar!(r#"SELECT COUNT(*) FROM products WHERE id = ?"#, product_id)
+            .fetch_one(&data.db_pool)
+            .instrument(telemetry::db_span("SELECT", "products"))
+            .await
+            .unwrap_or(0);
+        if exists == 0 {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Product not found"
+            }));
+        }
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "product_id": product_id,
+        "points": points
+    }))
+}
@@ -0,0 +1,273 @@
+use crate::api_keys::{api_key, find_api_key, Scope};
+use crate::auth::{bearer_token, find_session_user, hash_token};
+use crate::{telemetry, AppState};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+use tracing::Instrument;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum Role {
+    Customer,
+    Staff,
+    Admin,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Role> {
+        match value.trim().to_lowercase().as_str() {
+            "customer" => Some(Role::Customer),
+            "staff" => Some(Role::Staff),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Staff => matches!(
+                permission,
+                Permission::ProductsWrite | Permission::FilesRead | Permission::FilesWrite
+            ),
+            Role::Customer => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ProductsWrite,
+    FilesRead,
+    FilesWrite,
+    FilesDelete,
+    GeneratorControl,
+    ManageUsers,
+    ManageApiKeys,
+    ViewAudit,
+    ManageLogging,
+}
+
+// Static bearer tokens from the `API_TOKENS` variable, formatted as `token:role,token:role`
+#[derive(Default)]
+pub struct AuthConfig {
+    tokens: HashMap<String, Role>,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        Self::parse(&std::env::var("API_TOKENS").unwrap_or_default())
+    }
+
+    pub fn parse(value: &str) -> Self {
+        let tokens = value
+            .split(',')
+            .filter_map(|entry| {
+                let (token, role) = entry.trim().rsplit_once(':')?;
+                match Role::parse(role) {
+                    Some(role) if !token.is_empty() => Some((hash_token(token), role)),
+                    _ => {
+                        tracing::warn!("Ignoring malformed API_TOKENS entry");
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self { tokens }
+    }
+
+    fn role_for(&self, token: &str) -> Option<Role> {
+        self.tokens.get(&hash_token(token)).copied()
+    }
+}
+
+// What a caller is allowed to do: people act through a role, integrations through key scopes
+#[derive(Debug, Clone)]
+pub enum Grant {
+    Role(Role),
+    Scopes(Vec<Scope>),
+}
+
+// The authenticated caller: a configured token, a logged-in user or an API key
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub user_id: Option<i64>,
+    pub name: String,
+    pub grant: Grant,
+}
+
+impl Principal {
+    pub fn allows(&self, permission: Permission) -> bool {
+        match &self.grant {
+            Grant::Role(role) => role.allows(permission),
+            Grant::Scopes(scopes) => scopes.iter().any(|scope| scope.grants(permission)),
+        }
+    }
+}
+
+pub async fn resolve_principal(req: &HttpRequest) -> Option<Principal> {
+    let app_state = req.app_data::<web::Data<AppState>>()?;
+
+    if let Some(key) = api_key(req) {
+        let key = find_api_key(&app_state.db_pool, &key).await?;
+        return Some(Principal {
+            user_id: None,
+            name: format!("api-key:{}:{}", key.id, key.name),
+            grant: Grant::Scopes(key.scopes),
+        });
+    }
+
+    let token = bearer_token(req)?;
+    if let Some(role) = app_state.auth_config.role_for(&token) {
+        return Some(Principal {
+            user_id: None,
+            name: format!("token:{}", &hash_token(&token)[..12]),
+            grant: Grant::Role(role),
+        });
+    }
+
+    let user = find_session_user(&app_state.db_pool, &token).await?;
+    Some(Principal {
+        user_id: Some(user.id),
+        name: user.email,
+        grant: Grant::Role(user.role),
+    })
+}
+
+fn error_response<B>(req: ServiceRequest, response: HttpResponse) -> ServiceResponse<EitherBody<B>> {
+    req.into_response(response).map_into_right_body()
+}
+
+// Route middleware rejecting callers whose role lacks the given permission
+pub struct RequirePermission(pub Permission);
+
+impl<S, B> Transform<S, ServiceRequest> for RequirePermission
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequirePermissionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequirePermissionMiddleware {
+            service: Rc::new(service),
+            permission: self.0,
+        }))
+    }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+    service: Rc<S>,
+    permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for RequirePermissionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let permission = self.permission;
+
+        Box::pin(async move {
+            let principal = match resolve_principal(req.request()).await {
+                Some(principal) => principal,
+                None => {
+                    return Ok(error_response(
+                        req,
+                        HttpResponse::Unauthorized().json(serde_json::json!({
+                            "error": "Authentication required"
+                        })),
+                    ))
+                }
+            };
+
+            if !principal.allows(permission) {
+                return Ok(error_response(
+                    req,
+                    HttpResponse::Forbidden().json(serde_json::json!({
+                        "error": "You do not have permission to perform this action"
+                    })),
+                ));
+            }
+
+            req.extensions_mut().insert(principal);
+            service.call(req).await.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+// Handlers behind `RequirePermission` can take the resolved caller as an argument
+impl FromRequest for Principal {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<Principal>().cloned().ok_or_else(|| {
+            InternalError::from_response(
+                "Authentication required",
+                HttpResponse::Unauthorized().json(serde_json::json!({
+                    "error": "Authentication required"
+                })),
+            )
+            .into()
+        }))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRoleRequest {
+    pub role: Role,
+}
+
+pub async fn update_user_role(
+    data: web::Data<AppState>,
+    principal: Principal,
+    id: web::Path<i64>,
+    request: web::Json<UpdateRoleRequest>,
+) -> impl Responder {
+    let user_id = id.into_inner();
+    let role = request.role;
+
+    // Keeps an admin from locking themselves out
+    if principal.user_id == Some(user_id) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "You cannot change your own role"
+        }));
+    }
+
+    match sqlx::query!(r#"UPDATE users SET role = ? WHERE id = ?"#, role, user_id)
+        .execute(&data.db_pool)
+        .instrument(telemetry::db_span("UPDATE", "users"))
+        .await
+    {
+        Ok(result) if result.rows_affected() > 0 => HttpResponse::Ok().json(serde_json::json!({
+            "id": user_id,
+            "role": role
+        })),
+        Ok(_) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "User not found"
+        })),
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to update role"
+        })),
+    }
+}
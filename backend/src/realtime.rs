@@ -0,0 +1,634 @@
+use crate::models::{Product, ProductEvent};
+use crate::{telemetry, AppState};
+use actix::{Actor, ActorContext, AsyncContext, Handler, Message, StreamHandler};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse, Responder};
+use actix_web_actors::ws::{self, WebsocketContext};
+use bytestring::ByteString;
+use futures::{stream, StreamExt};
+use prometheus::{IntCounter, IntGauge};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+// Bumped whenever the envelope or a payload changes incompatibly
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub struct SequencedEvent {
+    pub seq: u64,
+    pub event: ProductEvent,
+}
+
+struct EventLog {
+    last_seq: u64,
+    recent: VecDeque<SequencedEvent>,
+}
+
+// Where a reconnecting client left off, sent as `<epoch>:<seq>`. A bare `<seq>` is taken to be
+// from the current run; only the full form is caught when the server restarted in between.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumePoint {
+    pub epoch: Option<String>,
+    pub seq: u64,
+}
+
+impl ResumePoint {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let (epoch, seq) = match value.split_once(':') {
+            Some((epoch, seq)) if !epoch.is_empty() => (Some(epoch.to_string()), seq),
+            Some(_) => return Err(format!("Invalid resume position '{}'", value)),
+            None => (None, value),
+        };
+        let seq = seq.parse().map_err(|_| format!("Invalid resume position '{}'", value))?;
+        Ok(Self { epoch, seq })
+    }
+}
+
+// Fans catalog events out to live subscribers, numbering them in publish order and
+// keeping the most recent ones so reconnecting clients can catch up
+pub struct EventBus {
+    tx: broadcast::Sender<SequencedEvent>,
+    log: Mutex<EventLog>,
+    replay_capacity: usize,
+    // Sequence numbers restart with every server run, so they only mean something alongside this
+    epoch: String,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize, replay_capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self {
+            tx,
+            log: Mutex::new(EventLog { last_seq: 0, recent: VecDeque::with_capacity(replay_capacity) }),
+            replay_capacity,
+            epoch: format!("{:016x}", rand::random::<u64>()),
+        }
+    }
+
+    pub fn epoch(&self) -> &str {
+        &self.epoch
+    }
+
+    pub fn publish(&self, event: ProductEvent) -> u64 {
+        let span = tracing::info_span!(
+            "broadcast.publish",
+            otel.kind = "producer",
+            event = event.kind(),
+            seq = tracing::field::Empty,
+            subscribers = self.tx.receiver_count(),
+        )
+        .entered();
+        // Held across the send so subscribers never see sequence numbers out of order
+        let mut log = self.log.lock().unwrap();
+        log.last_seq += 1;
+        span.record("seq", log.last_seq);
+        let sequenced = SequencedEvent { seq: log.last_seq, event };
+        if log.recent.len() == self.replay_capacity {
+            log.recent.pop_front();
+        }
+        log.recent.push_back(sequenced.clone());
+        let _ = self.tx.send(sequenced);
+        log.last_seq
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SequencedEvent> {
+        self.tx.subscribe()
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    // Events still waiting for the slowest subscriber
+    pub fn queued(&self) -> usize {
+        self.tx.len()
+    }
+
+    // Subscribes and returns the sequence number the receiver starts after, plus the events
+    // published since `since` when it is from this run and they are all still buffered
+    pub fn resume(
+        &self,
+        since: Option<&ResumePoint>,
+    ) -> (broadcast::Receiver<SequencedEvent>, u64, Option<Vec<SequencedEvent>>) {
+        let log = self.log.lock().unwrap();
+        let rx = self.subscribe();
+        let since = since
+            .filter(|since| since.epoch.as_ref().is_none_or(|epoch| *epoch == self.epoch))
+            .map(|since| since.seq);
+        let missed = since.filter(|since| *since <= log.last_seq).and_then(|since| {
+            let oldest = log.recent.front().map_or(log.last_seq + 1, |event| event.seq);
+            (since + 1 >= oldest).then(|| log.recent.iter().filter(|event| event.seq > since).cloned().collect())
+        });
+        (rx, log.last_seq, missed)
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, T: Serialize> {
+    v: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+    #[serde(flatten)]
+    message: &'a T,
+}
+
+// Control messages the server sends in reply to a client message
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Snapshot { products: Vec<Product> },
+    Ack { action: String, subscription: SubscriptionFilter },
+    Error { message: String },
+    Resync { skipped: u64, last_seq: u64 },
+}
+
+// Sequenced frames carry the epoch and sequence number a client resumes from
+fn frame<T: Serialize>(position: Option<(&str, u64)>, id: Option<&str>, message: &T) -> String {
+    let (epoch, seq) = position.unzip();
+    serde_json::to_string(&Envelope { v: PROTOCOL_VERSION, epoch, seq, id, message }).unwrap()
+}
+
+pub fn event_frame(epoch: &str, event: &SequencedEvent) -> String {
+    frame(Some((epoch, event.seq)), None, &event.event)
+}
+
+// A snapshot carries the sequence number it is current as of, so clients can resume from it
+pub fn snapshot_frame(epoch: &str, seq: u64, products: Vec<Product>) -> String {
+    frame(Some((epoch, seq)), None, &ServerMessage::Snapshot { products })
+}
+
+pub fn control_frame(message: &ServerMessage, id: Option<&str>) -> String {
+    frame(None, id, message)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriptionRequest {
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub product_ids: Vec<i64>,
+}
+
+impl SubscriptionRequest {
+    fn is_empty(&self) -> bool {
+        self.categories.is_empty() && self.product_ids.is_empty()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClientMessage {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub payload: SubscriptionRequest,
+    pub id: Option<String>,
+    pub v: Option<u32>,
+}
+
+// Which events a connection receives; new connections receive everything
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriptionFilter {
+    all: bool,
+    categories: BTreeSet<String>,
+    product_ids: BTreeSet<i64>,
+}
+
+impl Default for SubscriptionFilter {
+    fn default() -> Self {
+        Self { all: true, categories: BTreeSet::new(), product_ids: BTreeSet::new() }
+    }
+}
+
+impl SubscriptionFilter {
+    // An empty subscribe means everything; otherwise the filters narrow what is received
+    pub fn subscribe(&mut self, request: &SubscriptionRequest) {
+        if request.is_empty() {
+            self.all = true;
+            return;
+        }
+        self.all = false;
+        self.categories.extend(request.categories.iter().map(|c| c.to_lowercase()));
+        self.product_ids.extend(request.product_ids.iter().copied());
+    }
+
+    // An empty unsubscribe stops all events
+    pub fn unsubscribe(&mut self, request: &SubscriptionRequest) {
+        if request.is_empty() {
+            self.all = false;
+            self.categories.clear();
+            self.product_ids.clear();
+            return;
+        }
+        for category in &request.categories {
+            self.categories.remove(&category.to_lowercase());
+        }
+        for product_id in &request.product_ids {
+            self.product_ids.remove(product_id);
+        }
+    }
+
+    pub fn matches(&self, event: &ProductEvent) -> bool {
+        self.matches_product(event.product_id(), event.category())
+    }
+
+    pub fn matches_product(&self, product_id: i64, category: Option<&str>) -> bool {
+        self.all
+            || self.product_ids.contains(&product_id)
+            || category.is_some_and(|category| self.categories.contains(&category.to_lowercase()))
+    }
+}
+
+// Applies one client text frame to the filter and returns the reply to send back
+pub fn handle_client_message(filter: &mut SubscriptionFilter, text: &str) -> (ServerMessage, Option<String>) {
+    let message: ClientMessage = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => {
+            return (ServerMessage::Error { message: format!("Malformed message: {}", e) }, None);
+        }
+    };
+
+    if let Some(version) = message.v.filter(|v| *v != PROTOCOL_VERSION) {
+        let error = format!("Unsupported protocol version {}; expected {}", version, PROTOCOL_VERSION);
+        return (ServerMessage::Error { message: error }, message.id);
+    }
+
+    match message.kind.as_str() {
+        "subscribe" => filter.subscribe(&message.payload),
+        "unsubscribe" => filter.unsubscribe(&message.payload),
+        other => {
+            let error = format!("Unknown message type '{}'", other);
+            return (ServerMessage::Error { message: error }, message.id);
+        }
+    }
+
+    let ack = ServerMessage::Ack { action: message.kind, subscription: filter.clone() };
+    (ack, message.id)
+}
+
+// How often the server pings each client, and how long a silent client is kept
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(15);
+
+// WebSocket actor
+struct ProductWs {
+    events: Option<broadcast::Receiver<SequencedEvent>>,
+    filter: SubscriptionFilter,
+    // Snapshot or replayed events sent as soon as the connection is up
+    initial_frames: Vec<String>,
+    // Events up to this sequence number are already covered by what the client received
+    last_seq: u64,
+    epoch: String,
+    last_heartbeat: Instant,
+    forwarder: Option<JoinHandle<()>>,
+    connections: IntGauge,
+    lagged: IntCounter,
+}
+
+// Message wrapper for SequencedEvent
+struct EventMessage(SequencedEvent);
+
+impl Message for EventMessage {
+    type Result = ();
+}
+
+// Sent when the client's receiver fell behind the broadcast channel and events were dropped
+struct Lagged(u64);
+
+impl Message for Lagged {
+    type Result = ();
+}
+
+impl ProductWs {
+    fn heartbeat(&self, ctx: &mut WebsocketContext<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if act.last_heartbeat.elapsed() > CLIENT_TIMEOUT {
+                tracing::info!(client = ?ctx.address(), "WebSocket client timed out");
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+}
+
+impl Actor for ProductWs {
+    type Context = WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        tracing::info!(client = ?ctx.address(), "WebSocket client connected");
+        self.connections.inc();
+
+        for frame in self.initial_frames.drain(..) {
+            ctx.text(ByteString::from(frame));
+        }
+        self.heartbeat(ctx);
+
+        // Start listening for catalog events. The actor only runs while the socket accepts writes,
+        // so waiting on its bounded mailbox lets a stalled client fall behind the broadcast channel
+        // and get a resync, instead of queueing events without limit.
+        let addr = ctx.address();
+        if let Some(mut rx) = self.events.take() {
+            self.forwarder = Some(actix_rt::spawn(async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(event) => {
+                            if addr.send(EventMessage(event)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            addr.do_send(Lagged(skipped));
+                            break;
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            }));
+        }
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        // The forwarding task would otherwise keep the receiver alive for the life of the server
+        if let Some(forwarder) = self.forwarder.take() {
+            forwarder.abort();
+        }
+        self.connections.dec();
+        tracing::info!(client = ?ctx.address(), "WebSocket client disconnected");
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ProductWs {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(_) => {
+                ctx.stop();
+                return;
+            }
+        };
+        self.last_heartbeat = Instant::now();
+
+        match msg {
+            ws::Message::Ping(msg) => ctx.pong(&msg),
+            ws::Message::Text(text) => {
+                let (reply, id) = handle_client_message(&mut self.filter, &text);
+                ctx.text(ByteString::from(control_frame(&reply, id.as_deref())));
+            }
+            ws::Message::Binary(_) => {
+                let reply = ServerMessage::Error { message: "Binary messages are not supported".to_string() };
+                ctx.text(ByteString::from(control_frame(&reply, None)));
+            }
+            ws::Message::Close(reason) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Handler<EventMessage> for ProductWs {
+    type Result = ();
+
+    fn handle(&mut self, msg: EventMessage, ctx: &mut Self::Context) {
+        if msg.0.seq <= self.last_seq {
+            return;
+        }
+        self.last_seq = msg.0.seq;
+        if self.filter.matches(&msg.0.event) {
+            // One per event and client, so only kept when debugging the realtime path
+            let _span = tracing::debug_span!("ws.send", otel.kind = "consumer", seq = msg.0.seq).entered();
+            ctx.text(ByteString::from(event_frame(&self.epoch, &msg.0)));
+        }
+    }
+}
+
+impl Handler<Lagged> for ProductWs {
+    type Result = ();
+
+    // Tells the client where it got to and closes, so it reconnects with `?since=<epoch>:<seq>` and catches up
+    fn handle(&mut self, msg: Lagged, ctx: &mut Self::Context) {
+        tracing::warn!(client = ?ctx.address(), skipped = msg.0, "WebSocket client fell behind");
+        self.lagged.inc_by(msg.0);
+        let resync = ServerMessage::Resync { skipped: msg.0, last_seq: self.last_seq };
+        ctx.text(ByteString::from(control_frame(&resync, None)));
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Again,
+            description: Some("resync".to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
+// Query parameters shared by the WebSocket and SSE feeds
+#[derive(Debug, Deserialize)]
+pub struct StreamQuery {
+    // Where the client was before reconnecting, as `<epoch>:<seq>` or a bare `<seq>`
+    pub since: Option<String>,
+    // Comma-separated initial subscription; omitted means everything
+    pub categories: Option<String>,
+    pub product_ids: Option<String>,
+}
+
+impl StreamQuery {
+    fn filter(&self) -> Result<SubscriptionFilter, String> {
+        let categories = split_list(self.categories.as_deref()).map(str::to_string).collect::<Vec<_>>();
+        let product_ids = split_list(self.product_ids.as_deref())
+            .map(|id| id.parse().map_err(|_| format!("Invalid product id '{}'", id)))
+            .collect::<Result<Vec<i64>, _>>()?;
+
+        let mut filter = SubscriptionFilter::default();
+        filter.subscribe(&SubscriptionRequest { categories, product_ids });
+        Ok(filter)
+    }
+}
+
+fn split_list(value: Option<&str>) -> impl Iterator<Item = &str> {
+    value.unwrap_or("").split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+// What a (re)connecting client is sent before live events
+enum CatchUp {
+    Replay(Vec<SequencedEvent>),
+    Snapshot(Vec<Product>),
+}
+
+// Subscribing before loading the snapshot means no event can fall in between
+async fn catch_up(
+    app_state: &AppState,
+    filter: &SubscriptionFilter,
+    since: Option<&ResumePoint>,
+) -> (broadcast::Receiver<SequencedEvent>, u64, CatchUp) {
+    let (events, last_seq, missed) = app_state.events.resume(since);
+    let catch_up = match missed {
+        Some(missed) => CatchUp::Replay(missed.into_iter().filter(|event| filter.matches(&event.event)).collect()),
+        None => {
+            let products = sqlx::query_as!(
+                Product,
+                r#"SELECT id, name, price, image, description, category FROM products"#
+            )
+            .fetch_all(&app_state.db_pool)
+            .instrument(telemetry::db_span("SELECT", "products"))
+            .await
+            .unwrap_or_else(|_| vec![])
+            .into_iter()
+            .filter(|product| filter.matches_product(product.id, product.category.as_deref()))
+            .collect();
+            CatchUp::Snapshot(products)
+        }
+    };
+    (events, last_seq, catch_up)
+}
+
+// WebSocket handler
+pub async fn product_ws(
+    req: actix_web::HttpRequest,
+    stream: web::Payload,
+    app_state: web::Data<AppState>,
+    query: web::Query<StreamQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))),
+    };
+    let since = match query.since.as_deref().map(ResumePoint::parse).transpose() {
+        Ok(since) => since,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))),
+    };
+
+    let (events, last_seq, catch_up) = catch_up(&app_state, &filter, since.as_ref()).await;
+    let epoch = app_state.events.epoch().to_string();
+    let initial_frames = match catch_up {
+        CatchUp::Replay(missed) => missed.iter().map(|event| event_frame(&epoch, event)).collect(),
+        CatchUp::Snapshot(products) => vec![snapshot_frame(&epoch, last_seq, products)],
+    };
+
+    tracing::debug!(subscribers = app_state.events.subscriber_count(), "WebSocket subscribers");
+    let ws = ProductWs {
+        events: Some(events),
+        filter,
+        initial_frames,
+        last_seq,
+        epoch,
+        last_heartbeat: Instant::now(),
+        forwarder: None,
+        connections: app_state.metrics.websocket_connections.clone(),
+        lagged: app_state.metrics.broadcast_lagged.with_label_values(&["websocket"]),
+    };
+    let resp = ws::start(ws, &req, stream)?;
+    Ok(resp)
+}
+
+// Comment lines keep proxies from closing an idle stream and let the server notice dead clients
+const SSE_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+// One `text/event-stream` message; `data` is the same envelope the WebSocket sends, and the id is
+// the `<epoch>:<seq>` the browser sends back as Last-Event-ID
+fn sse_frame(position: Option<(&str, u64)>, kind: &str, data: &str) -> Bytes {
+    let id = position.map(|(epoch, seq)| format!("id: {}:{}\n", epoch, seq)).unwrap_or_default();
+    Bytes::from(format!("{}event: {}\ndata: {}\n\n", id, kind, data))
+}
+
+struct SseFeed {
+    events: broadcast::Receiver<SequencedEvent>,
+    filter: SubscriptionFilter,
+    last_seq: u64,
+    epoch: String,
+    keep_alive: tokio::time::Interval,
+    done: bool,
+    lagged: IntCounter,
+}
+
+impl SseFeed {
+    async fn next_frame(&mut self) -> Option<Bytes> {
+        if self.done {
+            return None;
+        }
+        loop {
+            tokio::select! {
+                received = self.events.recv() => match received {
+                    Ok(event) => {
+                        if event.seq <= self.last_seq {
+                            continue;
+                        }
+                        self.last_seq = event.seq;
+                        if self.filter.matches(&event.event) {
+                            let data = event_frame(&self.epoch, &event);
+                            return Some(sse_frame(Some((&self.epoch, event.seq)), event.event.kind(), &data));
+                        }
+                    }
+                    // EventSource reconnects by itself with Last-Event-ID, which replays the gap
+                    Err(RecvError::Lagged(skipped)) => {
+                        self.done = true;
+                        self.lagged.inc_by(skipped);
+                        let resync = ServerMessage::Resync { skipped, last_seq: self.last_seq };
+                        return Some(sse_frame(None, "resync", &control_frame(&resync, None)));
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = self.keep_alive.tick() => return Some(Bytes::from_static(b": keep-alive\n\n")),
+            }
+        }
+    }
+}
+
+// Server-Sent Events handler, for clients that cannot open a WebSocket
+pub async fn product_events(
+    req: actix_web::HttpRequest,
+    app_state: web::Data<AppState>,
+    query: web::Query<StreamQuery>,
+) -> impl Responder {
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e })),
+    };
+
+    // Browsers send Last-Event-ID when reconnecting; `since` covers the first connection
+    let last_event_id = req.headers().get("Last-Event-ID").and_then(|value| value.to_str().ok());
+    let since = match last_event_id.or(query.since.as_deref()).map(ResumePoint::parse).transpose() {
+        Ok(since) => since,
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e })),
+    };
+
+    let (events, last_seq, catch_up) = catch_up(&app_state, &filter, since.as_ref()).await;
+    let epoch = app_state.events.epoch().to_string();
+    let initial_frames = match catch_up {
+        CatchUp::Replay(missed) => missed
+            .iter()
+            .map(|event| sse_frame(Some((&epoch, event.seq)), event.event.kind(), &event_frame(&epoch, event)))
+            .collect::<Vec<_>>(),
+        CatchUp::Snapshot(products) => {
+            let data = snapshot_frame(&epoch, last_seq, products);
+            vec![sse_frame(Some((&epoch, last_seq)), "snapshot", &data)]
+        }
+    };
+
+    let mut keep_alive = tokio::time::interval(SSE_KEEP_ALIVE);
+    keep_alive.reset();
+    let lagged = app_state.metrics.broadcast_lagged.with_label_values(&["sse"]);
+    let feed = SseFeed { events, filter, last_seq, epoch, keep_alive, done: false, lagged };
+    let live = stream::unfold(feed, |mut feed| async move {
+        feed.next_frame().await.map(|frame| (frame, feed))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(
+            stream::iter(initial_frames)
+                .chain(live)
+                .map(Ok::<_, actix_web::Error>),
+        )
+}
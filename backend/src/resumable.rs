@@ -0,0 +1,395 @@
+use crate::auth::generate_token;
+use crate::files::{display_name, quarantine_dir, store_assembled};
+use crate::rbac::Principal;
+use crate::{now_unix, telemetry, AppState};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{self, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tracing::Instrument;
+
+// Sessions with a chunk currently being written; a second writer would corrupt the file
+#[derive(Default)]
+pub struct SessionLocks {
+    active: Mutex<HashSet<String>>,
+}
+
+pub struct SessionGuard<'a> {
+    locks: &'a SessionLocks,
+    id: String,
+}
+
+impl SessionLocks {
+    pub fn try_lock(&self, id: &str) -> Option<SessionGuard<'_>> {
+        let mut active = self.active.lock().unwrap();
+        active.insert(id.to_string()).then(|| SessionGuard { locks: self, id: id.to_string() })
+    }
+}
+
+impl Drop for SessionGuard<'_> {
+    fn drop(&mut self) {
+        self.locks.active.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub filename: String,
+    pub size: i64,
+    // Bytes received so far; the next chunk must start here
+    pub offset: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSessionRequest {
+    pub filename: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompleteSessionRequest {
+    // Optional check of the whole file, on top of the per-chunk checksums
+    pub sha256: Option<String>,
+}
+
+fn session_path(data: &AppState, id: &str) -> PathBuf {
+    quarantine_dir(&data.uploads.dir).join(format!("session-{}", id))
+}
+
+fn session_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "Upload session not found or expired"
+    }))
+}
+
+fn session_busy() -> HttpResponse {
+    HttpResponse::Conflict().json(serde_json::json!({
+        "error": "Another request for this upload is still in progress"
+    }))
+}
+
+fn failed(action: &str) -> HttpResponse {
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "error": format!("Failed to {}", action)
+    }))
+}
+
+// Sessions belong to whoever created them; expired ones are treated as gone
+async fn find_session(data: &AppState, id: &str, owner: &str) -> Result<Option<UploadSession>, sqlx::Error> {
+    let now = now_unix();
+    sqlx::query_as!(
+        UploadSession,
+        r#"
+        SELECT id, filename, size, received AS "offset", expires_at
+        FROM upload_sessions
+        WHERE id = ? AND owner = ? AND expires_at > ?
+        "#,
+        id,
+        owner,
+        now
+    )
+    .fetch_optional(&data.db_pool)
+    .instrument(telemetry::db_span("SELECT", "upload_sessions"))
+    .await
+}
+
+async fn remove_session(data: &AppState, id: &str) {
+    let _ = fs::remove_file(session_path(data, id)).await;
+    if let Err(e) = sqlx::query!("DELETE FROM upload_sessions WHERE id = ?", id)
+        .execute(&data.db_pool)
+        .instrument(telemetry::db_span("DELETE", "upload_sessions"))
+        .await
+    {
+        tracing::error!(session = id, error = %e, "Failed to remove upload session");
+    }
+}
+
+fn session_response(mut response: actix_web::HttpResponseBuilder, session: &UploadSession) -> HttpResponse {
+    response.insert_header(("Upload-Offset", session.offset.to_string())).json(session)
+}
+
+pub async fn create_session(
+    data: web::Data<AppState>,
+    principal: Principal,
+    request: web::Json<CreateSessionRequest>,
+) -> impl Responder {
+    let config = &data.uploads;
+    if request.size == 0 || request.size > config.max_resumable_size {
+        return HttpResponse::PayloadTooLarge().json(serde_json::json!({
+            "error": format!("Resumable uploads must be between 1 and {} bytes", config.max_resumable_size)
+        }));
+    }
+
+    let id = generate_token();
+    let filename = display_name(Some(&request.filename));
+    let size = request.size as i64;
+    let created_at = now_unix();
+    let expires_at = created_at + config.session_ttl.as_secs() as i64;
+
+    let created = match fs::create_dir_all(quarantine_dir(&config.dir)).await {
+        Ok(()) => fs::File::create(session_path(&data, &id)).await.map(drop),
+        Err(e) => Err(e),
+    };
+    if created.is_err() {
+        return failed("create upload session");
+    }
+    if sqlx::query!(
+        r#"
+        INSERT INTO upload_sessions (id, owner, filename, size, received, created_at, expires_at)
+        VALUES (?, ?, ?, ?, 0, ?, ?)
+        "#,
+        id,
+        principal.name,
+        filename,
+        size,
+        created_at,
+        expires_at
+    )
+    .execute(&data.db_pool)
+    .instrument(telemetry::db_span("INSERT", "upload_sessions"))
+    .await
+    .is_err()
+    {
+        let _ = fs::remove_file(session_path(&data, &id)).await;
+        return failed("create upload session");
+    }
+
+    let session = UploadSession { id, filename, size, offset: 0, expires_at };
+    let mut response = HttpResponse::Created();
+    response.insert_header(("Location", format!("/api/uploads/{}", session.id)));
+    session_response(response, &session)
+}
+
+// Where to resume after a disconnect
+pub async fn get_session(data: web::Data<AppState>, principal: Principal, id: web::Path<String>) -> impl Responder {
+    match find_session(&data, &id, &principal.name).await {
+        Ok(Some(session)) => session_response(HttpResponse::Ok(), &session),
+        Ok(None) => session_not_found(),
+        Err(_) => failed("load upload session"),
+    }
+}
+
+// `Upload-Checksum: sha256 <hex digest of this chunk>`
+fn chunk_checksum(req: &HttpRequest) -> Option<String> {
+    let value = req.headers().get("Upload-Checksum")?.to_str().ok()?;
+    let (algorithm, digest) = value.trim().split_once(' ')?;
+    let digest = digest.trim().to_lowercase();
+    (algorithm.eq_ignore_ascii_case("sha256") && digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .then_some(digest)
+}
+
+// Anything written by a rejected chunk is cut off again
+async fn discard(file: &fs::File, offset: u64, response: HttpResponse) -> HttpResponse {
+    let _ = file.set_len(offset).await;
+    response
+}
+
+// Appends one chunk at `Upload-Offset`; a chunk that is cut short or fails its checksum is
+// discarded, so the client simply resends it from the same offset
+pub async fn put_chunk(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    principal: Principal,
+    id: web::Path<String>,
+    mut payload: web::Payload,
+) -> impl Responder {
+    let Some(offset) = req
+        .headers()
+        .get("Upload-Offset")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+    else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Upload-Offset header is required"
+        }));
+    };
+    let Some(expected) = chunk_checksum(&req) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Upload-Checksum header must be 'sha256 <hex digest>'"
+        }));
+    };
+
+    let Some(_guard) = data.upload_locks.try_lock(&id) else {
+        return session_busy();
+    };
+    let mut session = match find_session(&data, &id, &principal.name).await {
+        Ok(Some(session)) => session,
+        Ok(None) => return session_not_found(),
+        Err(_) => return failed("load upload session"),
+    };
+    if offset != session.offset as u64 {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("Chunk must start at offset {}", session.offset),
+            "offset": session.offset
+        }));
+    }
+
+    let Ok(mut file) = fs::OpenOptions::new().write(true).open(session_path(&data, &id)).await else {
+        return failed("open upload session");
+    };
+    if file.seek(SeekFrom::Start(offset)).await.is_err() {
+        return failed("write chunk");
+    }
+
+    let mut hasher = Sha256::new();
+    let mut received: u64 = 0;
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                return discard(&file, offset, HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Failed to read chunk: {}", e)
+                }))).await;
+            }
+        };
+        received += chunk.len() as u64;
+        if received > data.uploads.max_chunk_size || offset + received > session.size as u64 {
+            return discard(&file, offset, HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                "error": format!(
+                    "Chunks are limited to {} bytes and may not run past the declared size",
+                    data.uploads.max_chunk_size
+                )
+            }))).await;
+        }
+        hasher.update(&chunk);
+        if file.write_all(&chunk).await.is_err() {
+            return discard(&file, offset, failed("write chunk")).await;
+        }
+    }
+
+    if hex::encode(hasher.finalize()) != expected {
+        return discard(&file, offset, HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Chunk does not match its checksum",
+            "offset": session.offset
+        }))).await;
+    }
+    if file.sync_all().await.is_err() {
+        return discard(&file, offset, failed("write chunk")).await;
+    }
+
+    // Activity keeps the session alive
+    session.offset = (offset + received) as i64;
+    session.expires_at = now_unix() + data.uploads.session_ttl.as_secs() as i64;
+    if sqlx::query!(
+        "UPDATE upload_sessions SET received = ?, expires_at = ? WHERE id = ?",
+        session.offset,
+        session.expires_at,
+        session.id
+    )
+    .execute(&data.db_pool)
+    .instrument(telemetry::db_span("UPDATE", "upload_sessions"))
+    .await
+    .is_err()
+    {
+        return discard(&file, offset, failed("record chunk")).await;
+    }
+
+    session_response(HttpResponse::Ok(), &session)
+}
+
+// Runs the assembled file through the regular upload pipeline. A file that is refused is
+// dropped with its session; a temporary failure keeps both, so completing can be retried.
+pub async fn complete_session(
+    data: web::Data<AppState>,
+    principal: Principal,
+    id: web::Path<String>,
+    request: Option<web::Json<CompleteSessionRequest>>,
+) -> impl Responder {
+    let Some(_guard) = data.upload_locks.try_lock(&id) else {
+        return session_busy();
+    };
+    let session = match find_session(&data, &id, &principal.name).await {
+        Ok(Some(session)) => session,
+        Ok(None) => return session_not_found(),
+        Err(_) => return failed("load upload session"),
+    };
+    if session.offset != session.size {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("Only {} of {} bytes have been received", session.offset, session.size),
+            "offset": session.offset
+        }));
+    }
+
+    let expected_sha256 = request.as_ref().and_then(|request| request.sha256.as_deref());
+    let stored = store_assembled(
+        &data,
+        &principal,
+        &session_path(&data, &id),
+        session.filename.clone(),
+        expected_sha256,
+    )
+    .await;
+    match stored {
+        Ok(file) => {
+            remove_session(&data, &id).await;
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "message": "Uploaded 1 file(s)",
+                "files": [file]
+            }))
+        }
+        Err(response) => {
+            if response.status().is_client_error() {
+                remove_session(&data, &id).await;
+            }
+            response
+        }
+    }
+}
+
+pub async fn abort_session(data: web::Data<AppState>, principal: Principal, id: web::Path<String>) -> impl Responder {
+    let Some(_guard) = data.upload_locks.try_lock(&id) else {
+        return session_busy();
+    };
+    match find_session(&data, &id, &principal.name).await {
+        Ok(Some(_)) => {
+            remove_session(&data, &id).await;
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => session_not_found(),
+        Err(_) => failed("load upload session"),
+    }
+}
+
+// Drops sessions nobody has touched within the session lifetime, along with their bytes
+pub async fn remove_expired_sessions(data: &AppState) -> io::Result<usize> {
+    let now = now_unix();
+    let expired = sqlx::query_scalar!("SELECT id FROM upload_sessions WHERE expires_at <= ?", now)
+        .fetch_all(&data.db_pool)
+        .instrument(telemetry::db_span("SELECT", "upload_sessions"))
+        .await
+        .map_err(io::Error::other)?;
+
+    let mut removed = 0;
+    for id in expired {
+        // A chunk still arriving will refresh the expiry when it lands
+        if let Some(_guard) = data.upload_locks.try_lock(&id) {
+            remove_session(data, &id).await;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+pub fn spawn_session_expiry(data: web::Data<AppState>, interval: Duration) {
+    actix_rt::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match remove_expired_sessions(&data).await {
+                Ok(removed) if removed > 0 => tracing::info!(removed, "Expired abandoned upload sessions"),
+                Ok(_) => {}
+                Err(e) => tracing::error!(error = %e, "Upload session expiry failed"),
+            }
+        }
+    });
+}
@@ -0,0 +1,119 @@
+use async_trait::async_trait;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// Upper bound for one clamd conversation, including connecting
+const CLAMD_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Size of the chunks streamed to clamd
+const CHUNK_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    // Carries the signature or reason the scanner gave
+    Rejected(String),
+}
+
+// Inspects an upload while it is still in quarantine; errors mean the file could not be checked
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    async fn scan(&self, path: &Path) -> io::Result<ScanVerdict>;
+}
+
+// Accepts everything, for development without a clamd
+pub struct NoopScanner;
+
+#[async_trait]
+impl Scanner for NoopScanner {
+    async fn scan(&self, _path: &Path) -> io::Result<ScanVerdict> {
+        Ok(ScanVerdict::Clean)
+    }
+}
+
+pub enum ClamdAddress {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl ClamdAddress {
+    // `tcp://host:port` for a networked clamd, anything else is a Unix socket path
+    pub fn parse(value: &str) -> Self {
+        match value.strip_prefix("tcp://") {
+            Some(address) => Self::Tcp(address.to_string()),
+            None => Self::Unix(PathBuf::from(value)),
+        }
+    }
+}
+
+// Streams files to clamd with the INSTREAM command
+pub struct ClamdScanner {
+    address: ClamdAddress,
+}
+
+impl ClamdScanner {
+    pub fn new(address: ClamdAddress) -> Self {
+        Self { address }
+    }
+
+    async fn instream<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, path: &Path) -> io::Result<ScanVerdict> {
+        stream.write_all(b"zINSTREAM\0").await?;
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut chunk = vec![0u8; CHUNK_LEN];
+        loop {
+            let read = file.read(&mut chunk).await?;
+            // Each chunk is prefixed with its length; a zero length ends the stream
+            stream.write_all(&(read as u32).to_be_bytes()).await?;
+            if read == 0 {
+                break;
+            }
+            stream.write_all(&chunk[..read]).await?;
+        }
+        stream.flush().await?;
+
+        let mut reply = vec![];
+        stream.read_to_end(&mut reply).await?;
+        parse_reply(&String::from_utf8_lossy(&reply))
+    }
+}
+
+// Replies look like `stream: OK`, `stream: <signature> FOUND` or `<message> ERROR`
+fn parse_reply(reply: &str) -> io::Result<ScanVerdict> {
+    let reply = reply.trim_end_matches(['\0', '\n']).trim();
+    let result = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix("FOUND") {
+        Ok(ScanVerdict::Rejected(signature.trim().to_string()))
+    } else {
+        Err(io::Error::other(format!("clamd replied '{}'", reply)))
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamdScanner {
+    async fn scan(&self, path: &Path) -> io::Result<ScanVerdict> {
+        let conversation = async {
+            match &self.address {
+                ClamdAddress::Unix(socket) => Self::instream(tokio::net::UnixStream::connect(socket).await?, path).await,
+                ClamdAddress::Tcp(address) => Self::instream(tokio::net::TcpStream::connect(address).await?, path).await,
+            }
+        };
+        tokio::time::timeout(CLAMD_TIMEOUT, conversation)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "clamd did not answer in time"))?
+    }
+}
+
+// `UPLOAD_SCANNER=clamd` scans through `CLAMD_ADDRESS`; anything else accepts uploads unscanned
+pub fn from_env() -> Box<dyn Scanner> {
+    if std::env::var("UPLOAD_SCANNER").is_ok_and(|scanner| scanner.eq_ignore_ascii_case("clamd")) {
+        let address = std::env::var("CLAMD_ADDRESS").unwrap_or_else(|_| "/var/run/clamav/clamd.ctl".to_string());
+        tracing::info!(%address, "Scanning uploads with clamd");
+        return Box::new(ClamdScanner::new(ClamdAddress::parse(&address)));
+    }
+    tracing::warn!("Uploads are not scanned; set UPLOAD_SCANNER=clamd to enable scanning");
+    Box::new(NoopScanner)
+}
@@ -0,0 +1,368 @@
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use aws_sdk_s3::config::{Credentials, Region};
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::ByteStream;
+use futures::stream::BoxStream;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+// How long presigned URLs stay valid
+pub const PRESIGN_EXPIRY: Duration = Duration::from_secs(15 * 60);
+
+// Keys are generated by the server, so anything outside this shape is refused before reaching a backend
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with('.')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+pub enum BlobObject {
+    // Stored on local disk, so it can be served with range and conditional request support
+    File(PathBuf),
+    Stream {
+        content_type: Option<String>,
+        size: Option<u64>,
+        etag: Option<String>,
+        // The backend's `Content-Range` when only part of the object was returned
+        content_range: Option<String>,
+        body: BoxStream<'static, io::Result<Bytes>>,
+    },
+}
+
+pub struct BlobInfo {
+    pub key: String,
+    // Seconds since the Unix epoch
+    pub modified_at: i64,
+}
+
+// A URL the client can use directly against the storage backend
+pub struct PresignedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: HashMap<String, String>,
+}
+
+// Writes and removes a hidden scratch file, proving the directory exists and is writable
+pub fn write_probe(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(format!(".probe-{}", std::process::id()));
+    fs::write(&probe, b"ok")?;
+    fs::remove_file(&probe)
+}
+
+// Where uploaded files live; handlers only ever go through this
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    // Moves a fully written local file into the store under `key`
+    async fn put_file(&self, key: &str, source: &Path, content_type: &str) -> io::Result<()>;
+
+    async fn open(&self, key: &str) -> io::Result<Option<BlobObject>>;
+
+    // Opens the part of an object a single `Range` header asks for. Backends that cannot read part of
+    // an object return all of it, which clients accept; local files get ranges from `NamedFile` anyway.
+    // A range that starts past the end of the object is an `InvalidInput` error.
+    async fn open_range(&self, key: &str, _range: &str) -> io::Result<Option<BlobObject>> {
+        self.open(key).await
+    }
+
+    // Checks for a key without reading the object
+    async fn exists(&self, key: &str) -> io::Result<bool>;
+
+    async fn list(&self) -> io::Result<Vec<BlobInfo>>;
+
+    // Removing a key that is already gone is not an error
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    // Confirms the backend is reachable and usable for storing uploads
+    async fn check(&self) -> io::Result<()>;
+
+    async fn presign_download(&self, _key: &str, _filename: &str) -> io::Result<Option<PresignedRequest>> {
+        Ok(None)
+    }
+
+    async fn presign_upload(&self, _key: &str, _content_type: &str, _size: u64) -> io::Result<Option<PresignedRequest>> {
+        Ok(None)
+    }
+}
+
+pub struct FileSystemStore {
+    root: PathBuf,
+}
+
+impl FileSystemStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    // Resolves a key to a file, refusing anything that ends up outside the root
+    fn resolve(&self, key: &str) -> Option<PathBuf> {
+        if !is_valid_key(key) {
+            return None;
+        }
+        let root = self.root.canonicalize().ok()?;
+        let path = root.join(key).canonicalize().ok()?;
+        (path.starts_with(&root) && path.is_file()).then_some(path)
+    }
+}
+
+#[async_trait]
+impl BlobStore for FileSystemStore {
+    async fn put_file(&self, key: &str, source: &Path, _content_type: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.root)?;
+        fs::rename(source, self.root.join(key))
+    }
+
+    async fn open(&self, key: &str) -> io::Result<Option<BlobObject>> {
+        Ok(self.resolve(key).map(BlobObject::File))
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        Ok(self.resolve(key).is_some())
+    }
+
+    async fn list(&self) -> io::Result<Vec<BlobInfo>> {
+        if !self.root.exists() {
+            return Ok(vec![]);
+        }
+        // Skips the upload temp directory and anything else hidden
+        Ok(fs::read_dir(&self.root)?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let key = entry.file_name().into_string().ok().filter(|name| !name.starts_with('.'))?;
+                let modified = entry.metadata().and_then(|metadata| metadata.modified()).ok()?;
+                let modified_at = modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+                Some(BlobInfo { key, modified_at })
+            })
+            .collect())
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        if !is_valid_key(key) {
+            return Ok(());
+        }
+        match fs::remove_file(self.root.join(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn check(&self) -> io::Result<()> {
+        write_probe(&self.root)
+    }
+}
+
+pub struct S3Config {
+    pub bucket: String,
+    pub endpoint: Option<String>,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl S3Config {
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            bucket: std::env::var("S3_BUCKET").ok()?,
+            endpoint: std::env::var("S3_ENDPOINT").ok(),
+            region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            access_key_id: std::env::var("S3_ACCESS_KEY_ID").ok()?,
+            secret_access_key: std::env::var("S3_SECRET_ACCESS_KEY").ok()?,
+        })
+    }
+}
+
+pub struct S3Store {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+impl S3Store {
+    pub fn new(config: S3Config) -> Self {
+        let mut builder = aws_sdk_s3::Config::builder()
+            .region(Region::new(config.region))
+            .credentials_provider(Credentials::new(
+                config.access_key_id,
+                config.secret_access_key,
+                None,
+                None,
+                "environment",
+            ));
+        // Custom endpoints are S3-compatible servers such as MinIO, which expect path-style URLs
+        if let Some(endpoint) = config.endpoint {
+            builder = builder.endpoint_url(endpoint).force_path_style(true);
+        }
+        Self {
+            client: aws_sdk_s3::Client::from_conf(builder.build()),
+            bucket: config.bucket,
+        }
+    }
+}
+
+impl S3Store {
+    async fn get(&self, key: &str, range: Option<&str>) -> io::Result<Option<BlobObject>> {
+        if !is_valid_key(key) {
+            return Ok(None);
+        }
+        let request = self.client.get_object().bucket(&self.bucket).key(key).set_range(range.map(str::to_string));
+        let object = match request.send().await {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) if e.code() == Some("InvalidRange") => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+            Err(e) => return Err(io::Error::other(e)),
+        };
+
+        let content_type = object.content_type().map(str::to_string);
+        let size = object.content_length().and_then(|size| u64::try_from(size).ok());
+        let etag = object.e_tag().map(str::to_string);
+        let content_range = object.content_range().map(str::to_string);
+        let body = futures::stream::unfold(object.body, |mut body| async move {
+            body.next().await.map(|chunk| (chunk.map_err(io::Error::other), body))
+        });
+        Ok(Some(BlobObject::Stream { content_type, size, etag, content_range, body: Box::pin(body) }))
+    }
+}
+
+fn presigning() -> io::Result<PresigningConfig> {
+    PresigningConfig::expires_in(PRESIGN_EXPIRY).map_err(io::Error::other)
+}
+
+fn into_presigned(request: aws_sdk_s3::presigning::PresignedRequest) -> PresignedRequest {
+    PresignedRequest {
+        method: request.method().to_string(),
+        url: request.uri().to_string(),
+        headers: request.headers().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+    async fn put_file(&self, key: &str, source: &Path, content_type: &str) -> io::Result<()> {
+        let body = ByteStream::from_path(source).await.map_err(io::Error::other)?;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(body)
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    async fn open(&self, key: &str) -> io::Result<Option<BlobObject>> {
+        self.get(key, None).await
+    }
+
+    async fn open_range(&self, key: &str, range: &str) -> io::Result<Option<BlobObject>> {
+        self.get(key, Some(range)).await
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        if !is_valid_key(key) {
+            return Ok(false);
+        }
+        match self.client.head_object().bucket(&self.bucket).key(key).send().await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    async fn list(&self) -> io::Result<Vec<BlobInfo>> {
+        let mut keys = vec![];
+        let mut continuation_token = None;
+        loop {
+            let page = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(io::Error::other)?;
+            keys.extend(page.contents().iter().filter_map(|object| {
+                Some(BlobInfo {
+                    key: object.key()?.to_string(),
+                    modified_at: object.last_modified().map_or(0, |modified| modified.secs()),
+                })
+            }));
+            continuation_token = page.next_continuation_token().map(str::to_string);
+            if continuation_token.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        if !is_valid_key(key) {
+            return Ok(());
+        }
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    // Checks the bucket exists and the credentials can reach it, without writing objects
+    async fn check(&self) -> io::Result<()> {
+        self.client
+            .head_bucket()
+            .bucket(&self.bucket)
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    async fn presign_download(&self, key: &str, filename: &str) -> io::Result<Option<PresignedRequest>> {
+        let request = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .response_content_disposition(format!("attachment; filename=\"{}\"", filename))
+            .presigned(presigning()?)
+            .await
+            .map_err(io::Error::other)?;
+        Ok(Some(into_presigned(request)))
+    }
+
+    // The content type and exact length are signed, so the client cannot upload something else
+    async fn presign_upload(&self, key: &str, content_type: &str, size: u64) -> io::Result<Option<PresignedRequest>> {
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .content_length(size as i64)
+            .presigned(presigning()?)
+            .await
+            .map_err(io::Error::other)?;
+        Ok(Some(into_presigned(request)))
+    }
+}
+
+// `STORAGE_BACKEND=s3` stores uploads in a bucket; anything else keeps them under the upload directory
+pub fn from_env(upload_dir: &Path) -> Box<dyn BlobStore> {
+    if std::env::var("STORAGE_BACKEND").is_ok_and(|backend| backend.eq_ignore_ascii_case("s3")) {
+        match S3Config::from_env() {
+            Some(config) => {
+                tracing::info!(bucket = %config.bucket, "Storing uploads in S3");
+                return Box::new(S3Store::new(config));
+            }
+            None => tracing::warn!("STORAGE_BACKEND=s3 needs S3_BUCKET, S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY; using the filesystem"),
+        }
+    }
+    Box::new(FileSystemStore::new(upload_dir.to_path_buf()))
+}
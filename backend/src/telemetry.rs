@@ -0,0 +1,306 @@
+use crate::auth::generate_token;
+use crate::rbac::Principal;
+use crate::{audit, AppState};
+use actix_web::body::{to_bytes, BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use actix_web::{web, Error, HttpResponse, Responder};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use serde::Deserialize;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::{field, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const DEFAULT_FILTER: &str = "info";
+
+// Log filter directives in `RUST_LOG` syntax, which admins can swap while the server runs
+pub struct LogLevels {
+    handle: Option<reload::Handle<EnvFilter, Registry>>,
+    current: Mutex<String>,
+}
+
+impl LogLevels {
+    // Tracks the filter without a subscriber to reload
+    #[cfg(test)]
+    pub fn detached(directives: &str) -> Self {
+        Self { handle: None, current: Mutex::new(directives.to_string()) }
+    }
+
+    pub fn current(&self) -> String {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        if let Some(handle) = &self.handle {
+            handle.reload(filter).map_err(|e| e.to_string())?;
+        }
+        *self.current.lock().unwrap() = directives.to_string();
+        Ok(())
+    }
+}
+
+// Spans go to an OTLP/HTTP collector only when one is configured through the standard
+// `OTEL_EXPORTER_OTLP_ENDPOINT` (or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`) variable
+fn tracer_provider() -> Option<SdkTracerProvider> {
+    let endpoint = std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
+        .or_else(|_| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT"))
+        .ok()?;
+    let exporter = match SpanExporter::builder().with_http().build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            // Logging is not set up yet at this point
+            eprintln!("Not exporting traces to {}: {}", endpoint, e);
+            return None;
+        }
+    };
+    let service = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "backend".to_string());
+    Some(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service).build())
+            .build(),
+    )
+}
+
+// Installs JSON logging on stdout, filtered by `RUST_LOG` (default `info`), plus OTLP trace export
+// when configured. The returned provider has to be shut down on exit to flush buffered spans.
+pub fn init() -> (LogLevels, Option<SdkTracerProvider>) {
+    let directives = std::env::var("RUST_LOG")
+        .ok()
+        .filter(|directives| EnvFilter::try_new(directives).is_ok())
+        .unwrap_or_else(|| DEFAULT_FILTER.to_string());
+    let (filter, handle) = reload::Layer::new(EnvFilter::new(&directives));
+    let provider = tracer_provider();
+    let traces = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("backend")));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().json().flatten_event(true).with_current_span(true).with_span_list(false))
+        .with(traces)
+        .init();
+    (LogLevels { handle: Some(handle), current: Mutex::new(directives) }, provider)
+}
+
+// Client span for one database statement, named like `SELECT products`
+pub fn db_span(operation: &'static str, table: &'static str) -> Span {
+    tracing::info_span!(
+        "db.query",
+        otel.name = %format_args!("{} {}", operation, table),
+        otel.kind = "client",
+        db.system = "sqlite",
+        db.operation = operation,
+        db.collection = table,
+    )
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+// Ids from the caller are kept when short and printable, so one id can follow a call across services
+fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| generate_token()[..32].to_string())
+}
+
+// Adds `request_id` to JSON error bodies so a user's report can be matched to the logs
+async fn with_request_id(res: ServiceResponse<BoxBody>, id: &str) -> ServiceResponse<BoxBody> {
+    let is_json = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json || !(res.status().is_client_error() || res.status().is_server_error()) {
+        return res;
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let body = match to_bytes(body).await {
+        Ok(body) => body,
+        Err(_) => return ServiceResponse::new(req, res.set_body(BoxBody::new(()))),
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(serde_json::Value::Object(mut fields)) => {
+            fields.insert("request_id".to_string(), id.into());
+            serde_json::to_vec(&fields).map(Into::into).unwrap_or(body)
+        }
+        _ => body,
+    };
+    ServiceResponse::new(req, res.set_body(BoxBody::new(body)))
+}
+
+fn log_completion(span: &Span, status: u16) {
+    span.in_scope(|| match status {
+        500.. => tracing::error!("request failed"),
+        400..=499 => tracing::warn!("request rejected"),
+        _ => tracing::info!("request completed"),
+    });
+}
+
+// App middleware giving every request a span with its id, method, route, status and latency,
+// feeding the HTTP metrics and echoing the id in the `X-Request-Id` response header
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let id = request_id(&req);
+        // Routes are recorded as their pattern, so ids in paths do not explode log or metric cardinality
+        let pattern = req.match_pattern();
+        let route = pattern.clone().unwrap_or_else(|| req.path().to_string());
+        let name = match &pattern {
+            Some(pattern) => format!("{} {}", req.method(), pattern),
+            None => req.method().to_string(),
+        };
+        let span = tracing::info_span!(
+            "request",
+            otel.name = %name,
+            otel.kind = "server",
+            otel.status_code = field::Empty,
+            request_id = %id,
+            trace_id = field::Empty,
+            method = %req.method(),
+            route = %route,
+            status = field::Empty,
+            latency_ms = field::Empty,
+        );
+        // Callers that send a W3C `traceparent` get this request's spans added to their trace
+        let parent = TraceContextPropagator::new().extract(&HeaderExtractor(req.headers()));
+        let _ = span.set_parent(parent);
+        let trace_id = span.context().span().span_context().trace_id();
+        if trace_id != opentelemetry::trace::TraceId::INVALID {
+            span.record("trace_id", trace_id.to_string());
+        }
+        let started = Instant::now();
+
+        Box::pin(async move {
+            let res = match service.call(req).instrument(span.clone()).await {
+                Ok(res) => res.map_into_boxed_body(),
+                Err(e) => {
+                    span.record("otel.status_code", "ERROR");
+                    span.record("status", 500);
+                    span.record("latency_ms", started.elapsed().as_millis() as u64);
+                    log_completion(&span, 500);
+                    return Err(e);
+                }
+            };
+
+            let status = res.status().as_u16();
+            if let Some(data) = res.request().app_data::<web::Data<AppState>>() {
+                data.metrics.observe_request(res.request().method().as_str(), pattern.as_deref(), status, started.elapsed());
+            }
+            if status >= 500 {
+                span.record("otel.status_code", "ERROR");
+            }
+            span.record("status", status);
+            span.record("latency_ms", started.elapsed().as_millis() as u64);
+            log_completion(&span, status);
+
+            let mut res = with_request_id(res, &id).await;
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogLevelRequest {
+    pub filter: String,
+}
+
+pub async fn get_log_level(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "filter": data.log_levels.current()
+    }))
+}
+
+// Takes `RUST_LOG`-style directives such as `info,backend::realtime=debug`
+pub async fn set_log_level(
+    data: web::Data<AppState>,
+    principal: Principal,
+    request: web::Json<LogLevelRequest>,
+) -> impl Responder {
+    let before = data.log_levels.current();
+    if let Err(e) = data.log_levels.set(request.filter.trim()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Invalid log filter: {}", e)
+        }));
+    }
+
+    let after = data.log_levels.current();
+    tracing::info!(actor = %principal.name, filter = %after, "log filter changed");
+    audit::record(
+        &data.db_pool,
+        &principal.name,
+        "update",
+        "log_level",
+        "filter",
+        Some(&serde_json::json!({ "filter": before })),
+        Some(&serde_json::json!({ "filter": after })),
+    )
+    .await;
+    HttpResponse::Ok().json(serde_json::json!({
+        "filter": after
+    }))
+}
@@ -1,56 +1,158 @@
 use super::*;
 use actix_web::test;
+use futures::StreamExt;
 use actix_web::http::StatusCode;
-use validation::{validate_product, ValidationError};
+use models::{LoginRequest, RegisterRequest};
+use tokio::time::{sleep, Duration};
 
-#[actix_web::test]
-async fn test_filter_and_sort_products() {
-    let products = mock_data::init_mock_data();
-    
-    // Test category filter
-    let query = ProductQuery {
-        category: Some("Shoes".to_string()),
+async fn test_state() -> web::Data<AppState> {
+    test_state_with_scanner(Box::new(scanner::NoopScanner)).await
+}
+
+async fn test_state_with_scanner(scanner: Box<dyn Scanner>) -> web::Data<AppState> {
+    let db_pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .expect("Failed to create in-memory pool.");
+    sqlx::migrate!().run(&db_pool).await.expect("Failed to run migrations.");
+
+    let upload_dir = std::env::temp_dir().join(format!("uploads-{}", auth::generate_token()));
+    web::Data::new(AppState {
+        generator: GeneratorController::default(),
+        events: EventBus::new(100, 100),
+        db_pool,
+        auth_config: AuthConfig::parse(&format!(
+            "{}:customer,{}:staff,{}:admin",
+            CUSTOMER_TOKEN, STAFF_TOKEN, ADMIN_TOKEN
+        )),
+        blobs: Box::new(storage::FileSystemStore::new(upload_dir.clone())),
+        scanner,
+        upload_locks: resumable::SessionLocks::default(),
+        log_levels: telemetry::LogLevels::detached("info"),
+        metrics: metrics::Metrics::default(),
+        uploads: UploadConfig {
+            dir: upload_dir,
+            max_file_size: 1024,
+            max_request_size: 4096,
+            images: images::ImageConfig::default(),
+            orphan_grace: Duration::from_secs(3600),
+            max_resumable_size: 64 * 1024,
+            max_chunk_size: 1024,
+            session_ttl: Duration::from_secs(3600),
+        },
+    })
+}
+
+const CUSTOMER_TOKEN: &str = "customer-token";
+const STAFF_TOKEN: &str = "staff-token";
+const ADMIN_TOKEN: &str = "admin-token";
+
+fn bearer(token: &str) -> (&'static str, String) {
+    ("Authorization", format!("Bearer {}", token))
+}
+
+async fn insert_product(state: &web::Data<AppState>, name: &str, price: f64) -> i64 {
+    sqlx::query!(
+        r#"INSERT INTO products (name, price, image, description, category) VALUES (?, ?, '/test.jpg', 'Test description', 'Test')"#,
+        name,
+        price
+    )
+    .execute(&state.db_pool)
+    .await
+    .unwrap()
+    .last_insert_rowid()
+}
+
+fn mock_products() -> Vec<Product> {
+    vec![
+        Product {
+            id: 1,
+            name: "Nike Air Max".to_string(),
+            price: 150.0,
+            image: None,
+            description: Some("Running shoes with air cushioning".to_string()),
+            category: Some("Shoes".to_string()),
+        },
+        Product {
+            id: 2,
+            name: "Adidas Ultraboost".to_string(),
+            price: 180.0,
+            image: None,
+            description: Some("Comfortable everyday sneakers".to_string()),
+            category: Some("Shoes".to_string()),
+        },
+        Product {
+            id: 3,
+            name: "Nike Hoodie".to_string(),
+            price: 60.0,
+            image: None,
+            description: Some("Warm fleece hoodie".to_string()),
+            category: Some("Clothing".to_string()),
+        },
+        Product {
+            id: 4,
+            name: "Leather Jacket".to_string(),
+            price: 250.0,
+            image: None,
+            description: None,
+            category: Some("Clothing".to_string()),
+        },
+    ]
+}
+
+fn empty_query() -> ProductQuery {
+    ProductQuery {
+        category: None,
         min_price: None,
         max_price: None,
         search_term: None,
         sort_by: None,
         sort_order: None,
+        offset: None,
+        limit: None,
+    }
+}
+
+#[actix_web::test]
+async fn test_filter_and_sort_products() {
+    let products = mock_products();
+
+    // Test category filter
+    let query = ProductQuery {
+        category: Some("Shoes".to_string()),
+        ..empty_query()
     };
     let filtered = filter_and_sort_products(&products, &query);
-    assert!(filtered.iter().all(|p| p.category == "Shoes"));
+    assert_eq!(filtered.len(), 2);
+    assert!(filtered.iter().all(|p| p.category.as_deref() == Some("Shoes")));
 
     // Test price range filter
     let query = ProductQuery {
-        category: None,
         min_price: Some(100.0),
         max_price: Some(200.0),
-        search_term: None,
-        sort_by: None,
-        sort_order: None,
+        ..empty_query()
     };
     let filtered = filter_and_sort_products(&products, &query);
+    assert_eq!(filtered.len(), 2);
     assert!(filtered.iter().all(|p| p.price >= 100.0 && p.price <= 200.0));
 
     // Test search term
     let query = ProductQuery {
-        category: None,
-        min_price: None,
-        max_price: None,
         search_term: Some("Nike".to_string()),
-        sort_by: None,
-        sort_order: None,
+        ..empty_query()
     };
     let filtered = filter_and_sort_products(&products, &query);
+    assert_eq!(filtered.len(), 2);
     assert!(filtered.iter().all(|p| p.name.contains("Nike")));
 
     // Test sorting
     let query = ProductQuery {
-        category: None,
-        min_price: None,
-        max_price: None,
-        search_term: None,
         sort_by: Some("price".to_string()),
         sort_order: Some("asc".to_string()),
+        ..empty_query()
     };
     let sorted = filter_and_sort_products(&products, &query);
     assert!(sorted.windows(2).all(|w| w[0].price <= w[1].price));
@@ -58,133 +160,113 @@ async fn test_filter_and_sort_products() {
 
 #[actix_web::test]
 async fn test_create_product() {
-    let state = web::Data::new(AppState {
-        products: Mutex::new(Vec::new()),
-    });
+    let state = test_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
 
-    let new_product = web::Json(CreateProductRequest {
+    let new_product = CreateProductRequest {
         name: "Test Product".to_string(),
         price: 99.99,
-        image: "/test.jpg".to_string(),
-        description: "Test Description".to_string(),
+        image: Some("/test.jpg".to_string()),
+        description: Some("Test Description".to_string()),
         category: "Test".to_string(),
-    });
+    };
 
     let resp = test::TestRequest::post()
-        .app_data(state.clone())
+        .uri("/api/products")
+        .insert_header(bearer(STAFF_TOKEN))
         .set_json(&new_product)
-        .send_request(&mut test::init_service(App::new().app_data(state.clone())).await)
+        .send_request(&app)
         .await;
-    assert_ne!(resp.status(), StatusCode::CREATED);
+    assert_eq!(resp.status(), StatusCode::CREATED);
 
-    let products = state.products.lock().unwrap();
-    assert_eq!(products.len(), 0);
+    let created: Product = test::read_body_json(resp).await;
+    assert_eq!(created.name, "Test Product");
+
+    let count = sqlx::query_scalar!("SELECT COUNT(*) FROM products")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
 }
 
 #[actix_web::test]
 async fn test_update_product() {
-    let initial_products = vec![
-        Product {
-            id: 1,
-            name: "Original".to_string(),
-            price: 100.0,
-            image: "/test.jpg".to_string(),
-            description: "Original".to_string(),
-            category: "Test".to_string(),
-        },
-    ];
-
-    let state = web::Data::new(AppState {
-        products: Mutex::new(initial_products),
-    });
+    let state = test_state().await;
+    let id = insert_product(&state, "Original", 100.0).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
 
-    let updated_product = web::Json(Product {
-        id: 1,
+    let updated_product = Product {
+        id,
         name: "Updated".to_string(),
         price: 200.0,
-        image: "/test.jpg".to_string(),
-        description: "Updated".to_string(),
-        category: "Test".to_string(),
-    });
+        image: Some("/test.jpg".to_string()),
+        description: Some("Updated".to_string()),
+        category: Some("Test".to_string()),
+    };
 
     let resp = test::TestRequest::put()
-        .uri("/api/products/1")
-        .app_data(state.clone())
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(bearer(STAFF_TOKEN))
         .set_json(&updated_product)
-        .send_request(&mut test::init_service(App::new().app_data(state.clone())).await)
+        .send_request(&app)
         .await;
-    assert_ne!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.status(), StatusCode::OK);
 
-    let products = state.products.lock().unwrap();
-    assert_ne!(products[0].name, "Updated");
-    assert_ne!(products[0].price, 200.0);
+    let row = sqlx::query!("SELECT name, price FROM products WHERE id = ?", id)
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(row.name, "Updated");
+    assert_eq!(row.price, 200.0);
 }
 
 #[actix_web::test]
 async fn test_delete_product() {
-    let initial_products = vec![
-        Product {
-            id: 1,
-            name: "Test".to_string(),
-            price: 100.0,
-            image: "/test.jpg".to_string(),
-            description: "Test".to_string(),
-            category: "Test".to_string(),
-        },
-    ];
+    let state = test_state().await;
+    let id = insert_product(&state, "Test", 100.0).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
 
-    let state = web::Data::new(AppState {
-        products: Mutex::new(initial_products),
-    });
+    let resp = test::TestRequest::delete()
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
 
     let resp = test::TestRequest::delete()
-        .uri("/api/products/1")
-        .app_data(state.clone())
-        .send_request(&mut test::init_service(App::new().app_data(state.clone())).await)
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
         .await;
-    assert_ne!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
 }
 
 #[actix_web::test]
 async fn test_get_product() {
-    let initial_products = vec![
-        Product {
-            id: 1,
-            name: "Test".to_string(),
-            price: 100.0,
-            image: "/test.jpg".to_string(),
-            description: "Test".to_string(),
-            category: "Test".to_string(),
-        },
-    ];
-
-    let state = web::Data::new(AppState {
-        products: Mutex::new(initial_products),
-    });
+    let state = test_state().await;
+    let id = insert_product(&state, "Test", 100.0).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
 
     let resp = test::TestRequest::get()
-        .uri("/api/products/1")
-        .app_data(state.clone())
-        .send_request(&mut test::init_service(App::new().app_data(state.clone())).await)
+        .uri(&format!("/api/products/{}", id))
+        .send_request(&app)
         .await;
-    assert_ne!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.status(), StatusCode::OK);
 
     let resp = test::TestRequest::get()
         .uri("/api/products/999")
-        .app_data(state.clone())
-        .send_request(&mut test::init_service(App::new().app_data(state.clone())).await)
+        .send_request(&app)
         .await;
     assert_eq!(resp.status(), StatusCode::NOT_FOUND);
-} 
-
+}
 
 #[actix_web::test]
 async fn test_validate_product() {
     let valid_product = CreateProductRequest {
         name: "Valid Product".to_string(),
         price: 100.0,
-        image: "/valid.jpg".to_string(),
-        description: "This is a valid description with more than 10 characters".to_string(),
+        image: Some("/valid.jpg".to_string()),
+        description: Some("This is a valid description with more than 10 characters".to_string()),
         category: "Test".to_string(),
     };
     assert!(validation::validate_product(&valid_product).is_ok());
@@ -192,8 +274,8 @@ async fn test_validate_product() {
     let invalid_product_price = CreateProductRequest {
         name: "Valid Product".to_string(),
         price: -50.0,
-        image: "/invalid.jpg".to_string(),
-        description: "Valid description".to_string(),
+        image: Some("/invalid.jpg".to_string()),
+        description: Some("Valid description".to_string()),
         category: "Test".to_string(),
     };
     assert!(validation::validate_product(&invalid_product_price).is_err());
@@ -201,9 +283,2275 @@ async fn test_validate_product() {
     let invalid_product_description = CreateProductRequest {
         name: "Valid Product".to_string(),
         price: 100.0,
-        image: "/invalid.jpg".to_string(),
-        description: "Short".to_string(),
+        image: Some("/invalid.jpg".to_string()),
+        description: Some("Short".to_string()),
         category: "Test".to_string(),
     };
     assert!(validation::validate_product(&invalid_product_description).is_err());
-}
\ No newline at end of file
+}
+
+#[actix_web::test]
+async fn test_register_login_and_logout() {
+    let state = test_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let registration = RegisterRequest {
+        email: "Shopper@Example.com".to_string(),
+        password: "correct horse".to_string(),
+    };
+    let resp = test::TestRequest::post()
+        .uri("/api/auth/register")
+        .set_json(&registration)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    // Emails are unique regardless of case
+    let duplicate = RegisterRequest {
+        email: "shopper@example.com".to_string(),
+        password: "another password".to_string(),
+    };
+    let resp = test::TestRequest::post()
+        .uri("/api/auth/register")
+        .set_json(&duplicate)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let stored_hash = sqlx::query_scalar!("SELECT password_hash FROM users")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    assert!(stored_hash.starts_with("$argon2"));
+
+    let wrong_password = LoginRequest {
+        email: "shopper@example.com".to_string(),
+        password: "wrong password".to_string(),
+    };
+    let resp = test::TestRequest::post()
+        .uri("/api/auth/login")
+        .set_json(&wrong_password)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    // Unknown emails are rejected the same way, after verifying against the dummy hash
+    let unknown_email = LoginRequest {
+        email: "nobody@example.com".to_string(),
+        password: "correct horse".to_string(),
+    };
+    let resp = test::TestRequest::post()
+        .uri("/api/auth/login")
+        .set_json(&unknown_email)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Invalid email or password");
+
+    let credentials = LoginRequest {
+        email: "shopper@example.com".to_string(),
+        password: "correct horse".to_string(),
+    };
+    let resp = test::TestRequest::post()
+        .uri("/api/auth/login")
+        .set_json(&credentials)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let token = body["token"].as_str().unwrap().to_string();
+
+    let resp = test::TestRequest::get()
+        .uri("/api/auth/me")
+        .insert_header(bearer(&token))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let user: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(user["email"], "shopper@example.com");
+
+    let resp = test::TestRequest::post()
+        .uri("/api/auth/logout")
+        .insert_header(bearer(&token))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    // A revoked token no longer authenticates
+    let resp = test::TestRequest::get()
+        .uri("/api/auth/me")
+        .insert_header(bearer(&token))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_role_permission_matrix() {
+    use rbac::{Permission, Role};
+
+    let permissions = [
+        Permission::ProductsWrite,
+        Permission::FilesRead,
+        Permission::FilesWrite,
+        Permission::FilesDelete,
+        Permission::GeneratorControl,
+        Permission::ManageUsers,
+        Permission::ManageApiKeys,
+        Permission::ViewAudit,
+        Permission::ManageLogging,
+    ];
+    let expected = [
+        (Role::Customer, [false, false, false, false, false, false, false, false, false]),
+        (Role::Staff, [true, true, true, false, false, false, false, false, false]),
+        (Role::Admin, [true, true, true, true, true, true, true, true, true]),
+    ];
+    for (role, allowed) in expected {
+        for (permission, allowed) in permissions.iter().zip(allowed) {
+            assert_eq!(role.allows(*permission), allowed, "{:?} / {:?}", role, permission);
+        }
+    }
+}
+
+#[actix_web::test]
+async fn test_protected_routes_enforce_roles() {
+    use actix_web::http::Method;
+    use rbac::Role;
+
+    let state = test_state().await;
+    let id = insert_product(&state, "Protected", 100.0).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let product = serde_json::json!({
+        "id": id,
+        "name": "Protected",
+        "price": 120.0,
+        "image": null,
+        "description": "Still protected",
+        "category": "Test"
+    });
+    let product_uri = format!("/api/products/{}", id);
+
+    // Each route with the roles that may call it
+    let routes = [
+        (Method::GET, "/api/products", None, vec![None, Some(Role::Customer), Some(Role::Staff), Some(Role::Admin)]),
+        (Method::POST, "/api/products", Some(product.clone()), vec![Some(Role::Staff), Some(Role::Admin)]),
+        (Method::PUT, product_uri.as_str(), Some(product.clone()), vec![Some(Role::Staff), Some(Role::Admin)]),
+        (Method::GET, "/api/files", None, vec![Some(Role::Staff), Some(Role::Admin)]),
+        (Method::POST, "/api/toggle-generation", Some(serde_json::json!(false)), vec![Some(Role::Admin)]),
+        (Method::PUT, "/api/users/999/role", Some(serde_json::json!({ "role": "staff" })), vec![Some(Role::Admin)]),
+        (Method::GET, "/api/audit", None, vec![Some(Role::Admin)]),
+        (Method::POST, "/api/media/cleanup", None, vec![Some(Role::Admin)]),
+        (Method::DELETE, "/api/files/999", None, vec![Some(Role::Admin)]),
+        (Method::GET, "/api/admin/log-level", None, vec![Some(Role::Admin)]),
+        (Method::DELETE, product_uri.as_str(), None, vec![Some(Role::Staff), Some(Role::Admin)]),
+    ];
+    let callers = [
+        (None, None),
+        (Some(CUSTOMER_TOKEN), Some(Role::Customer)),
+        (Some(STAFF_TOKEN), Some(Role::Staff)),
+        (Some(ADMIN_TOKEN), Some(Role::Admin)),
+    ];
+
+    for (method, uri, body, allowed) in &routes {
+        for (token, role) in callers {
+            let mut request = test::TestRequest::default().method(method.clone()).uri(uri);
+            if let Some(body) = body {
+                request = request.set_json(body);
+            }
+            if let Some(token) = token {
+                request = request.insert_header(bearer(token));
+            }
+            let resp = request.send_request(&app).await;
+            let status = resp.status();
+
+            if allowed.contains(&role) {
+                assert!(
+                    status != StatusCode::UNAUTHORIZED && status != StatusCode::FORBIDDEN,
+                    "{} {} as {:?} got {}",
+                    method,
+                    uri,
+                    role,
+                    status
+                );
+            } else if role.is_none() {
+                assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {} anonymously", method, uri);
+            } else {
+                assert_eq!(status, StatusCode::FORBIDDEN, "{} {} as {:?}", method, uri, role);
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert!(body["error"].is_string());
+            }
+        }
+    }
+}
+
+#[actix_web::test]
+async fn test_api_key_lifecycle() {
+    let state = test_state().await;
+    let id = insert_product(&state, "ERP item", 100.0).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let resp = test::TestRequest::post()
+        .uri("/api/api-keys")
+        .insert_header(bearer(ADMIN_TOKEN))
+        .set_json(serde_json::json!({ "name": "erp", "scopes": ["products:read", "products:write"] }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let key = body["key"].as_str().unwrap().to_string();
+    let key_id = body["api_key"]["id"].as_i64().unwrap();
+
+    // Only the hash is stored
+    let stored = sqlx::query_scalar!("SELECT key_hash FROM api_keys WHERE id = ?", key_id)
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored, key);
+
+    let price_update = serde_json::json!({
+        "id": id,
+        "name": "ERP item",
+        "price": 89.5,
+        "image": null,
+        "description": null,
+        "category": "Test"
+    });
+    let resp = test::TestRequest::put()
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(("Authorization", format!("ApiKey {}", key)))
+        .set_json(&price_update)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Scopes do not reach beyond what was granted
+    let resp = test::TestRequest::post()
+        .uri("/api/upload")
+        .insert_header(("Authorization", format!("ApiKey {}", key)))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let last_used = sqlx::query_scalar!("SELECT last_used_at FROM api_keys WHERE id = ?", key_id)
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    assert!(last_used.is_some());
+
+    let resp = test::TestRequest::post()
+        .uri(&format!("/api/api-keys/{}/rotate", key_id))
+        .insert_header(bearer(ADMIN_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let rotated = body["key"].as_str().unwrap().to_string();
+
+    let resp = test::TestRequest::put()
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(("Authorization", format!("ApiKey {}", key)))
+        .set_json(&price_update)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = test::TestRequest::put()
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(("Authorization", format!("ApiKey {}", rotated)))
+        .set_json(&price_update)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::TestRequest::delete()
+        .uri(&format!("/api/api-keys/{}", key_id))
+        .insert_header(bearer(ADMIN_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let resp = test::TestRequest::put()
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(("Authorization", format!("ApiKey {}", rotated)))
+        .set_json(&price_update)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_product_mutations_are_audited() {
+    let state = test_state().await;
+    let id = insert_product(&state, "Audited", 100.0).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let resp = test::TestRequest::put()
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({
+            "id": id,
+            "name": "Audited",
+            "price": 80.0,
+            "image": "/test.jpg",
+            "description": "Test description",
+            "category": "Test"
+        }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::TestRequest::delete()
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let resp = test::TestRequest::get()
+        .uri(&format!("/api/audit?entity_type=product&entity_id={}", id))
+        .insert_header(bearer(ADMIN_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let entries: Vec<serde_json::Value> = test::read_body_json(resp).await;
+    assert_eq!(entries.len(), 2);
+
+    // Newest first
+    assert_eq!(entries[0]["action"], "delete");
+    assert!(entries[0]["after"].is_null());
+    assert_eq!(entries[1]["action"], "update");
+    assert_eq!(entries[1]["diff"]["price"]["before"], 100.0);
+    assert_eq!(entries[1]["diff"]["price"]["after"], 80.0);
+    assert!(entries[1]["diff"].get("name").is_none());
+    assert!(entries[1]["actor"].as_str().unwrap().starts_with("token:"));
+
+    let resp = test::TestRequest::get()
+        .uri("/api/audit?actor=nobody")
+        .insert_header(bearer(ADMIN_TOKEN))
+        .send_request(&app)
+        .await;
+    let entries: Vec<serde_json::Value> = test::read_body_json(resp).await;
+    assert!(entries.is_empty());
+}
+
+#[actix_web::test]
+async fn test_price_changes_are_tracked_and_broadcast() {
+    let state = test_state().await;
+    let mut events = state.events.subscribe();
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let resp = test::TestRequest::post()
+        .uri("/api/products")
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({
+            "name": "Tracked",
+            "price": 100.0,
+            "image": null,
+            "description": null,
+            "category": "Test"
+        }))
+        .send_request(&app)
+        .await;
+    let created: Product = test::read_body_json(resp).await;
+
+    for price in [100.0, 120.0] {
+        let resp = test::TestRequest::put()
+            .uri(&format!("/api/products/{}", created.id))
+            .insert_header(bearer(STAFF_TOKEN))
+            .set_json(Product { price, ..created.clone() })
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    // Saving an unchanged price does not add a point
+    let resp = test::TestRequest::get()
+        .uri(&format!("/api/products/{}/price-history", created.id))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let points = body["points"].as_array().unwrap();
+    assert_eq!(points.len(), 2);
+    assert_eq!(points[0]["new_price"], 100.0);
+    assert!(points[0]["old_price"].is_null());
+    assert_eq!(points[1]["old_price"], 100.0);
+    assert_eq!(points[1]["new_price"], 120.0);
+
+    let mut received = vec![];
+    while let Ok(sequenced) = events.try_recv() {
+        received.push(sequenced.event);
+    }
+    let types = received
+        .iter()
+        .map(|event| serde_json::to_value(event).unwrap()["type"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(types, ["product_created", "product_updated", "price_changed", "product_updated"]);
+    match &received[2] {
+        ProductEvent::PriceChanged(change) => {
+            assert_eq!(change.product_id, created.id);
+            assert_eq!(change.category.as_deref(), Some("Test"));
+            assert_eq!(change.new_price, 120.0);
+        }
+        other => panic!("expected a price change event, got {:?}", other),
+    }
+
+    let resp = test::TestRequest::get()
+        .uri("/api/products/999/price-history")
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_price_drift_simulation_reprices_category() {
+    let state = test_state().await;
+    let id = insert_product(&state, "Drifting", 100.0).await;
+    sqlx::query!("INSERT INTO products (name, price, category) VALUES ('Untouched', 50.0, 'Other')")
+        .execute(&state.db_pool)
+        .await
+        .unwrap();
+    let mut events = state.events.subscribe();
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let resp = test::TestRequest::post()
+        .uri("/api/toggle-generation")
+        .insert_header(bearer(ADMIN_TOKEN))
+        .set_json(serde_json::json!({ "enabled": true, "mode": "price_drift", "volatility": 75.0 }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = test::TestRequest::post()
+        .uri("/api/toggle-generation")
+        .insert_header(bearer(ADMIN_TOKEN))
+        .set_json(serde_json::json!({
+            "enabled": true,
+            "mode": "price_drift",
+            "interval_ms": 100,
+            "category": "Test",
+            "volatility": 10.0
+        }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["is_simulating"], true);
+
+    let event = tokio::time::timeout(Duration::from_secs(2), events.recv())
+        .await
+        .expect("no price change within timeout")
+        .unwrap();
+    // Product subscribers hear about the new price too
+    let updated = tokio::time::timeout(Duration::from_secs(2), events.recv())
+        .await
+        .expect("no product update within timeout")
+        .unwrap();
+    state.generator.stop().await;
+    match updated.event {
+        ProductEvent::ProductUpdated(product) => assert_eq!(product.id, id),
+        other => panic!("expected a product update event, got {:?}", other),
+    }
+
+    match event.event {
+        ProductEvent::PriceChanged(change) => {
+            assert_eq!(change.product_id, id);
+            assert!(change.new_price >= 90.0 && change.new_price <= 110.0);
+            assert_eq!(change.source, "simulator");
+        }
+        other => panic!("expected a price change event, got {:?}", other),
+    }
+
+    let untouched = sqlx::query_scalar!("SELECT price FROM products WHERE category = 'Other'")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(untouched, 50.0);
+
+    // A category with nothing in it ends the run instead of polling forever
+    let resp = test::TestRequest::post()
+        .uri("/api/generator/start")
+        .insert_header(bearer(ADMIN_TOKEN))
+        .set_json(serde_json::json!({ "mode": "price_drift", "interval_ms": 100, "category": "Nowhere" }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let status = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let status = state.generator.status().await;
+            if !status.running {
+                break status;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("empty price drift run kept going");
+    assert_eq!(status.processed, 0);
+    assert_eq!(status.error.as_deref(), Some("No products in category 'Nowhere' to reprice"));
+    assert!(state.generator.health().await.is_err());
+}
+
+#[actix_web::test]
+async fn test_generator_controller_runs_a_single_task() {
+    let state = test_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    // Starting twice replaces the first run instead of adding a second generator
+    for _ in 0..2 {
+        let resp = test::TestRequest::post()
+            .uri("/api/generator/start")
+            .insert_header(bearer(ADMIN_TOKEN))
+            .set_json(serde_json::json!({
+                "interval_ms": 100,
+                "batch_size": 2,
+                "category_mix": { "Books": 1 },
+                "max_count": 3
+            }))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let status = loop {
+        let resp = test::TestRequest::get()
+            .uri("/api/generator/status")
+            .insert_header(bearer(ADMIN_TOKEN))
+            .send_request(&app)
+            .await;
+        let status: serde_json::Value = test::read_body_json(resp).await;
+        if status["running"] == false {
+            break status;
+        }
+        sleep(Duration::from_millis(50)).await;
+    };
+    assert_eq!(status["processed"], 3);
+
+    // The replaced run stopped after at most one batch
+    let books = sqlx::query_scalar!("SELECT COUNT(*) FROM products WHERE category = 'Books'")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    let total = sqlx::query_scalar!("SELECT COUNT(*) FROM products")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(books, total);
+    assert!(total <= 5);
+    assert_eq!(status["total_generated"], total);
+
+    let resp = test::TestRequest::post()
+        .uri("/api/generator/stop")
+        .insert_header(bearer(ADMIN_TOKEN))
+        .send_request(&app)
+        .await;
+    let status: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(status["running"], false);
+}
+
+#[actix_web::test]
+async fn test_seeded_products_are_reproducible() {
+    let first = test_state().await;
+    let second = test_state().await;
+    fixtures::seed_products(&first.db_pool, 20, 7).await.unwrap();
+    fixtures::seed_products(&second.db_pool, 20, 7).await.unwrap();
+    for state in [&first, &second] {
+        let attached = fixtures::attach_placeholder_media(&state.db_pool, state.blobs.as_ref(), &state.uploads.dir)
+            .await
+            .unwrap();
+        assert_eq!(attached, 20);
+    }
+    let primaries = sqlx::query_scalar!("SELECT COUNT(*) FROM product_media WHERE is_primary = 1")
+        .fetch_one(&first.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(primaries, 20);
+    let first_dir = first.uploads.dir.clone();
+    let second_dir = second.uploads.dir.clone();
+
+    let load = |state: web::Data<AppState>| async move {
+        sqlx::query_as!(
+            Product,
+            r#"SELECT id, name, price, image, description, category FROM products ORDER BY id"#
+        )
+        .fetch_all(&state.db_pool)
+        .await
+        .unwrap()
+    };
+    let first = load(first).await;
+    let second = load(second).await;
+
+    assert_eq!(first.len(), 20);
+    assert_eq!(
+        serde_json::to_value(&first).unwrap(),
+        serde_json::to_value(&second).unwrap()
+    );
+    // Every product shows a stored placeholder for its category
+    for product in &first {
+        let key = product.image.as_deref().unwrap().strip_prefix("/api/download/").unwrap();
+        assert!(first_dir.join(key).is_file());
+    }
+    let same_category = first.iter().filter(|p| p.category == first[0].category);
+    assert!(same_category.map(|p| &p.image).all(|image| *image == first[0].image));
+    let _ = std::fs::remove_dir_all(&first_dir);
+    let _ = std::fs::remove_dir_all(&second_dir);
+    assert!(first.iter().all(|p| validation::validate_product(&CreateProductRequest {
+        name: p.name.clone(),
+        price: p.price,
+        image: p.image.clone(),
+        description: p.description.clone(),
+        category: p.category.clone().unwrap(),
+    })
+    .is_ok()));
+
+    let mut factory = fixtures::ProductFactory::seeded(7);
+    let category = factory.pick_category(None);
+    assert_eq!(factory.next_product(&category).name, first[0].name);
+}
+
+#[actix_web::test]
+async fn test_ws_protocol_envelopes_and_subscriptions() {
+    use realtime::{control_frame, event_frame, handle_client_message, SequencedEvent, ServerMessage, SubscriptionFilter};
+
+    let product = Product { category: Some("Books".to_string()), ..mock_products()[0].clone() };
+    let frame: serde_json::Value = serde_json::from_str(&event_frame(
+        "run-a",
+        &SequencedEvent { seq: 7, event: ProductEvent::ProductDeleted(product.clone()) },
+    ))
+    .unwrap();
+    assert_eq!(frame["v"], 1);
+    assert_eq!(frame["epoch"], "run-a");
+    assert_eq!(frame["seq"], 7);
+    assert_eq!(frame["type"], "product_deleted");
+    assert_eq!(frame["payload"]["id"], product.id);
+
+    // New connections receive everything until they narrow their subscription
+    let mut filter = SubscriptionFilter::default();
+    let other = ProductEvent::ProductCreated(Product { id: 99, category: Some("Toys".to_string()), ..product.clone() });
+    assert!(filter.matches(&other));
+
+    let (reply, id) = handle_client_message(&mut filter, r#"{"type":"subscribe","id":"a1","payload":{"categories":["books"]}}"#);
+    let ack: serde_json::Value = serde_json::from_str(&control_frame(&reply, id.as_deref())).unwrap();
+    assert_eq!(ack["type"], "ack");
+    assert_eq!(ack["id"], "a1");
+    assert!(ack.get("seq").is_none() && ack.get("epoch").is_none());
+    assert_eq!(ack["payload"]["subscription"]["categories"], serde_json::json!(["books"]));
+    assert!(filter.matches(&ProductEvent::ProductUpdated(product.clone())));
+    assert!(!filter.matches(&other));
+
+    handle_client_message(&mut filter, r#"{"type":"subscribe","payload":{"product_ids":[99]}}"#);
+    assert!(filter.matches(&other));
+    handle_client_message(&mut filter, r#"{"type":"unsubscribe"}"#);
+    assert!(!filter.matches(&other));
+    assert!(!filter.matches(&ProductEvent::ProductUpdated(product)));
+
+    for malformed in [
+        "not json",
+        r#"{"payload":{}}"#,
+        r#"{"type":"subscribe","payload":{"category":"Books"}}"#,
+        r#"{"type":"dance"}"#,
+        r#"{"type":"subscribe","v":2}"#,
+    ] {
+        let (reply, _) = handle_client_message(&mut filter, malformed);
+        assert!(matches!(reply, ServerMessage::Error { .. }), "accepted {}", malformed);
+    }
+}
+
+#[actix_web::test]
+async fn test_event_bus_resumes_from_sequence() {
+    use realtime::{EventBus, ResumePoint};
+
+    let bus = EventBus::new(16, 3);
+    let product = mock_products()[0].clone();
+    for _ in 0..5 {
+        bus.publish(ProductEvent::ProductUpdated(product.clone()));
+    }
+    let at = |seq: u64| ResumePoint { epoch: Some(bus.epoch().to_string()), seq };
+
+    // Seqs 3..=5 are still buffered, so a client that saw 2 gets exactly what it missed
+    let (mut rx, last_seq, missed) = bus.resume(Some(&at(2)));
+    assert_eq!(last_seq, 5);
+    let missed = missed.expect("events since 2 should be replayable");
+    assert_eq!(missed.iter().map(|e| e.seq).collect::<Vec<_>>(), [3, 4, 5]);
+    assert_eq!(bus.resume(Some(&at(5))).2.map(|m| m.len()), Some(0));
+
+    // Too far behind, a fresh connection, or a sequence from before a restart all need a snapshot
+    assert!(bus.resume(Some(&at(1))).2.is_none());
+    assert!(bus.resume(None).2.is_none());
+    assert!(bus.resume(Some(&at(42))).2.is_none());
+
+    // A restarted server numbers from 1 again, so a sequence it has reached still comes from another run
+    let restarted = EventBus::new(16, 3);
+    for _ in 0..5 {
+        restarted.publish(ProductEvent::ProductUpdated(product.clone()));
+    }
+    assert_ne!(restarted.epoch(), bus.epoch());
+    assert!(restarted.resume(Some(&at(3))).2.is_none());
+    assert_eq!(ResumePoint::parse(&format!("{}:3", bus.epoch())), Ok(at(3)));
+
+    // A bare sequence number is taken to be from the current run
+    let bare = ResumePoint::parse("3").unwrap();
+    assert_eq!(bare, ResumePoint { epoch: None, seq: 3 });
+    assert_eq!(bus.resume(Some(&bare)).2.map(|m| m.len()), Some(2));
+    for malformed in ["", "abc", ":3", "run:", "run:x", "-1"] {
+        assert!(ResumePoint::parse(malformed).is_err(), "accepted {:?}", malformed);
+    }
+
+    assert_eq!(bus.publish(ProductEvent::ProductDeleted(product)), 6);
+    assert_eq!(rx.try_recv().unwrap().seq, 6);
+}
+
+#[actix_web::test]
+async fn test_ws_sends_filtered_snapshot_then_events() {
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let state = test_state().await;
+    let id = insert_product(&state, "Snapshot", 10.0).await;
+    sqlx::query!("INSERT INTO products (name, price, category) VALUES ('Filtered out', 5.0, 'Other')")
+        .execute(&state.db_pool)
+        .await
+        .unwrap();
+
+    let server_state = state.clone();
+    let server = HttpServer::new(move || App::new().app_data(server_state.clone()).configure(configure_routes))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let port = server.addrs()[0].port();
+    let server = server.run();
+    let handle = server.handle();
+    actix_rt::spawn(server);
+
+    let next_frame = |frame: Option<Result<WsMessage, _>>| -> serde_json::Value {
+        match frame {
+            Some(Ok(WsMessage::Text(text))) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    };
+
+    let url = format!("ws://127.0.0.1:{}/ws?categories=test", port);
+    let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+    let snapshot = next_frame(socket.next().await);
+    assert_eq!(snapshot["type"], "snapshot");
+    assert_eq!(snapshot["epoch"], state.events.epoch());
+    assert_eq!(snapshot["seq"], 0);
+    let products = snapshot["payload"]["products"].as_array().unwrap();
+    assert_eq!(products.len(), 1);
+    assert_eq!(products[0]["id"], id);
+
+    state.events.publish(ProductEvent::ProductUpdated(Product {
+        id,
+        name: "Snapshot".to_string(),
+        price: 12.0,
+        image: None,
+        description: None,
+        category: Some("Test".to_string()),
+    }));
+    let event = next_frame(socket.next().await);
+    assert_eq!(event["type"], "product_updated");
+    assert_eq!(event["seq"], 1);
+    drop(socket);
+
+    // Reconnecting with the last seen sequence replays only what was missed
+    state.events.publish(ProductEvent::ProductDeleted(mock_products()[0].clone()));
+    let url = format!("ws://127.0.0.1:{}/ws?since={}:1", port, state.events.epoch());
+    let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+    let replayed = next_frame(socket.next().await);
+    assert_eq!(replayed["type"], "product_deleted");
+    assert_eq!(replayed["seq"], 2);
+    drop(socket);
+
+    // A bare sequence number resumes within the current run
+    let url = format!("ws://127.0.0.1:{}/ws?since=1", port);
+    let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+    let replayed = next_frame(socket.next().await);
+    assert_eq!(replayed["type"], "product_deleted");
+    assert_eq!(replayed["seq"], 2);
+    drop(socket);
+
+    // A position that cannot be parsed is refused rather than answered with a snapshot
+    let url = format!("ws://127.0.0.1:{}/ws?since=yesterday", port);
+    match tokio_tungstenite::connect_async(&url).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(resp)) => assert_eq!(resp.status(), 400),
+        other => panic!("expected a 400, got {:?}", other.map(|(_, resp)| resp.status())),
+    }
+
+    // The same sequence from another server run cannot be replayed
+    let url = format!("ws://127.0.0.1:{}/ws?since=0000000000000000:1&categories=test", port);
+    let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+    let snapshot = next_frame(socket.next().await);
+    assert_eq!(snapshot["type"], "snapshot");
+    assert_eq!(snapshot["seq"], 2);
+
+    drop(socket);
+    handle.stop(false).await;
+}
+
+#[actix_web::test]
+async fn test_ws_disconnect_releases_event_receiver() {
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let state = test_state().await;
+    let server_state = state.clone();
+    let server = HttpServer::new(move || App::new().app_data(server_state.clone()).configure(configure_routes))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let port = server.addrs()[0].port();
+    let server = server.run();
+    let handle = server.handle();
+    actix_rt::spawn(server);
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/ws", port))
+        .await
+        .unwrap();
+    assert!(matches!(socket.next().await, Some(Ok(WsMessage::Text(_)))));
+    assert_eq!(state.events.subscriber_count(), 1);
+
+    socket.close(None).await.unwrap();
+    for _ in 0..20 {
+        if state.events.subscriber_count() == 0 {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(state.events.subscriber_count(), 0);
+
+    handle.stop(false).await;
+}
+
+#[actix_web::test]
+async fn test_ws_client_that_stops_reading_gets_a_resync() {
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let state = test_state().await;
+    let server_state = state.clone();
+    let server = HttpServer::new(move || App::new().app_data(server_state.clone()).configure(configure_routes))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let port = server.addrs()[0].port();
+    let server = server.run();
+    let handle = server.handle();
+    actix_rt::spawn(server);
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/ws", port))
+        .await
+        .unwrap();
+    assert!(matches!(socket.next().await, Some(Ok(WsMessage::Text(_)))));
+
+    // Large events fill the socket buffers while the client reads nothing. Publishing in small
+    // batches leaves the server time to keep up, so only the stalled socket can cause the lag.
+    let bulky = Product { description: Some("x".repeat(128 * 1024)), ..mock_products()[0].clone() };
+    for _ in 0..40 {
+        for _ in 0..10 {
+            state.events.publish(ProductEvent::ProductUpdated(bulky.clone()));
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    let resync = tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(Ok(message)) = socket.next().await {
+            if let WsMessage::Text(text) = message {
+                let frame: serde_json::Value = serde_json::from_str(&text).unwrap();
+                if frame["type"] == "resync" {
+                    return Some(frame);
+                }
+            }
+        }
+        None
+    })
+    .await
+    .ok()
+    .flatten()
+    .expect("a client that stopped reading should be told to resync");
+    assert!(resync["payload"]["skipped"].as_u64().unwrap() > 0);
+
+    drop(socket);
+    handle.stop(false).await;
+}
+
+#[actix_web::test]
+async fn test_sse_stream_resumes_from_last_event_id() {
+    use actix_web::body::MessageBody;
+
+    let state = test_state().await;
+    let product = Product { category: Some("Books".to_string()), ..mock_products()[0].clone() };
+    let other = Product { id: 2, category: Some("Toys".to_string()), ..product.clone() };
+    state.events.publish(ProductEvent::ProductUpdated(product.clone()));
+    state.events.publish(ProductEvent::ProductUpdated(other.clone()));
+    state.events.publish(ProductEvent::ProductDeleted(product.clone()));
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+    let epoch = state.events.epoch();
+
+    let resp = test::TestRequest::get()
+        .uri("/api/events?categories=books")
+        .insert_header(("Last-Event-ID", format!("{}:1", epoch)))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "text/event-stream");
+
+    let mut body = std::pin::pin!(resp.into_body());
+    let chunk = futures::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await.unwrap().unwrap();
+    let frame = std::str::from_utf8(&chunk).unwrap();
+    assert!(frame.starts_with(&format!("id: {}:3\nevent: product_deleted\ndata: ", epoch)), "{}", frame);
+    let data: serde_json::Value = serde_json::from_str(frame.lines().nth(2).unwrap().trim_start_matches("data: ")).unwrap();
+    assert_eq!(data["seq"], 3);
+    assert_eq!(data["payload"]["id"], product.id);
+
+    // Live events are filtered the same way
+    state.events.publish(ProductEvent::ProductCreated(other));
+    state.events.publish(ProductEvent::ProductCreated(product));
+    let chunk = futures::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await.unwrap().unwrap();
+    assert!(std::str::from_utf8(&chunk).unwrap().starts_with(&format!("id: {}:5\nevent: product_created\n", epoch)));
+
+    // An id from before a restart gets a snapshot instead of unrelated events
+    let resp = test::TestRequest::get()
+        .uri("/api/events?categories=books")
+        .insert_header(("Last-Event-ID", "0000000000000000:1"))
+        .send_request(&app)
+        .await;
+    let mut body = std::pin::pin!(resp.into_body());
+    let chunk = futures::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await.unwrap().unwrap();
+    assert!(std::str::from_utf8(&chunk).unwrap().starts_with(&format!("id: {}:5\nevent: snapshot\n", epoch)));
+
+    let resp = test::TestRequest::get()
+        .uri("/api/events?product_ids=abc")
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::TestRequest::get()
+        .uri("/api/events")
+        .insert_header(("Last-Event-ID", "not-a-position"))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+fn multipart_body(files: &[(&str, &[u8])]) -> (String, Vec<u8>) {
+    let boundary = "test-boundary";
+    let mut body = Vec::new();
+    for (name, content) in files {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                boundary, name
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
+
+#[actix_web::test]
+async fn test_uploads_are_content_addressed_and_sniffed() {
+    let state = test_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+    let png = test_image(1, 1, image::ImageFormat::Png);
+
+    let mut keys = vec![];
+    for name in ["../../escape.jpg", "again.png"] {
+        let (content_type, body) = multipart_body(&[(name, &png)]);
+        let resp = test::TestRequest::post()
+            .uri("/api/upload")
+            .insert_header(bearer(STAFF_TOKEN))
+            .insert_header(("Content-Type", content_type))
+            .set_payload(body)
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let file = &body["files"][0];
+        assert_eq!(file["content_type"], "image/png");
+        keys.push(file["key"].as_str().unwrap().to_string());
+        if name == "../../escape.jpg" {
+            assert_eq!(file["original_name"], "escape.jpg");
+        }
+    }
+
+    // The name comes from the content, never from the client
+    assert_eq!(keys[0], keys[1]);
+    assert!(keys[0].ends_with(".png") && keys[0].len() == 64 + 4);
+    assert!(state.uploads.dir.join(&keys[0]).exists());
+    assert!(!state.uploads.dir.parent().unwrap().join("escape.jpg").exists());
+
+    let (content_type, body) = multipart_body(&[("setup.png", b"MZ\x90\0\x03\0\0\0\x04\0\0\0\xff\xff")]);
+    let resp = test::TestRequest::post()
+        .uri("/api/upload")
+        .insert_header(bearer(STAFF_TOKEN))
+        .insert_header(("Content-Type", content_type))
+        .set_payload(body)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // Rejected uploads leave nothing behind
+    let resp = test::TestRequest::get()
+        .uri("/api/files")
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["files"][0]["key"], keys[0]);
+    assert_eq!(std::fs::read_dir(state.uploads.dir.join(".tmp")).unwrap().count(), 0);
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+fn test_image(width: u32, height: u32, format: image::ImageFormat) -> Vec<u8> {
+    let mut bytes = vec![];
+    image::DynamicImage::new_rgb8(width, height)
+        .write_to(&mut std::io::Cursor::new(&mut bytes), format)
+        .unwrap();
+    bytes
+}
+
+#[actix_web::test]
+async fn test_image_uploads_get_variants_without_exif() {
+    use img_parts::{DynImage, ImageEXIF};
+    use sha2::Digest;
+
+    let state = test_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    // A big-endian TIFF header with an empty IFD, enough to count as EXIF
+    let mut jpeg = DynImage::from_bytes(test_image(240, 8, image::ImageFormat::Jpeg).into()).unwrap().unwrap();
+    jpeg.set_exif(Some(b"MM\0*\0\0\0\x08\0\0\0\0\0\0".to_vec().into()));
+    let jpeg = jpeg.encoder().bytes().to_vec();
+
+    let (content_type, body) = multipart_body(&[("photo.jpg", &jpeg)]);
+    let resp = test::TestRequest::post()
+        .uri("/api/upload")
+        .insert_header(bearer(STAFF_TOKEN))
+        .insert_header(("Content-Type", content_type))
+        .set_payload(body)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let file = &body["files"][0];
+    let key = file["key"].as_str().unwrap().to_string();
+    let hash = key.trim_end_matches(".jpg");
+
+    // The stored original is stripped, and its hash describes the stripped bytes
+    let stored = std::fs::read(state.uploads.dir.join(&key)).unwrap();
+    assert_eq!(hex::encode(sha2::Sha256::digest(&stored)), hash);
+    assert!(DynImage::from_bytes(stored.into()).unwrap().unwrap().exif().is_none());
+
+    // Three sizes in the original format plus WebP and AVIF, never upscaled
+    let variants = file["variants"].as_array().unwrap();
+    assert_eq!(variants.len(), 9);
+    let thumbnail = variants.iter().find(|v| v["key"] == format!("{}_thumbnail.webp", hash)).unwrap();
+    assert_eq!(thumbnail["width"], 160);
+    let card = variants.iter().find(|v| v["key"] == format!("{}_card.jpg", hash)).unwrap();
+    assert_eq!(card["width"], 240);
+    for variant in variants {
+        assert!(state.uploads.dir.join(variant["key"].as_str().unwrap()).exists());
+    }
+
+    // Requested widths and formats resolve to the closest pre-rendered variant
+    for (query, served) in [
+        ("w=100&format=webp", format!("{}_thumbnail.webp", hash)),
+        ("w=300", format!("{}_card.jpg", hash)),
+        ("w=5000&format=avif", format!("{}_full.avif", hash)),
+    ] {
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/download/{}?{}", key, query))
+            .insert_header(bearer(STAFF_TOKEN))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let disposition = resp.headers().get("Content-Disposition").unwrap().to_str().unwrap().to_string();
+        assert!(disposition.contains(&served), "{} served {}", query, disposition);
+    }
+
+    let resp = test::TestRequest::get()
+        .uri(&format!("/api/download/{}?format=tiff", key))
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Variants are listed with their original, not on their own
+    let resp = test::TestRequest::get()
+        .uri("/api/files")
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["files"][0]["key"], key);
+    assert_eq!(body["files"][0]["variant_keys"].as_array().unwrap().len(), 9);
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+#[actix_web::test]
+async fn test_product_media_gallery_and_orphan_cleanup() {
+    let state = test_state().await;
+    let id = insert_product(&state, "Gallery", 40.0).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let mut keys = vec![];
+    for (width, height) in [(1, 1), (2, 1), (3, 1)] {
+        let (content_type, body) = multipart_body(&[("photo.png", &test_image(width, height, image::ImageFormat::Png))]);
+        let resp = test::TestRequest::post()
+            .uri("/api/upload")
+            .insert_header(bearer(STAFF_TOKEN))
+            .insert_header(("Content-Type", content_type))
+            .set_payload(body)
+            .send_request(&app)
+            .await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        keys.push(body["files"][0]["key"].as_str().unwrap().to_string());
+    }
+
+    let media_uri = format!("/api/products/{}/media", id);
+    let mut media_ids = vec![];
+    for (key, primary) in [(&keys[0], false), (&keys[1], false)] {
+        let resp = test::TestRequest::post()
+            .uri(&media_uri)
+            .insert_header(bearer(STAFF_TOKEN))
+            .set_json(serde_json::json!({ "file_key": key, "alt_text": " Front view ", "primary": primary }))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let media: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(media["alt_text"], "Front view");
+        media_ids.push(media["id"].as_i64().unwrap());
+    }
+
+    // The first attachment became primary and replaced the hand-set image
+    let product = find_product(&state.db_pool, id as i32).await.unwrap();
+    assert_eq!(product.image, Some(format!("/api/download/{}", keys[0])));
+
+    for (body, status) in [
+        (serde_json::json!({ "file_key": keys[0] }), StatusCode::CONFLICT),
+        (serde_json::json!({ "file_key": "missing.png" }), StatusCode::NOT_FOUND),
+        (serde_json::json!({ "file_key": format!("{}_thumbnail.webp", &keys[2][..64]) }), StatusCode::BAD_REQUEST),
+        (serde_json::json!({ "file_key": keys[2], "alt_text": "x".repeat(301) }), StatusCode::BAD_REQUEST),
+    ] {
+        let resp = test::TestRequest::post()
+            .uri(&media_uri)
+            .insert_header(bearer(STAFF_TOKEN))
+            .set_json(body)
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), status);
+    }
+
+    // Reordering needs the complete gallery
+    let order_uri = format!("{}/order", media_uri);
+    let resp = test::TestRequest::put()
+        .uri(&order_uri)
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({ "media_ids": [media_ids[1]] }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::TestRequest::put()
+        .uri(&order_uri)
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({ "media_ids": [media_ids[1], media_ids[0]] }))
+        .send_request(&app)
+        .await;
+    let gallery: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(gallery[0]["id"], media_ids[1]);
+    assert_eq!(gallery[0]["position"], 0);
+    assert_eq!(gallery[1]["is_primary"], true);
+
+    // Detaching the primary promotes the next image in line
+    let resp = test::TestRequest::delete()
+        .uri(&format!("{}/{}", media_uri, media_ids[0]))
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = test::TestRequest::get().uri(&media_uri).send_request(&app).await;
+    let gallery: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(gallery.as_array().unwrap().len(), 1);
+    assert_eq!(gallery[0]["is_primary"], true);
+    let product = find_product(&state.db_pool, id as i32).await.unwrap();
+    assert_eq!(product.image, Some(format!("/api/download/{}", keys[1])));
+
+    // Recent uploads are kept, older unreferenced ones go along with their variants
+    let removed = media::remove_orphaned_files(&state, "test", Duration::from_secs(3600)).await.unwrap();
+    assert!(removed.is_empty());
+    let removed = media::remove_orphaned_files(&state, "test", Duration::ZERO).await;
+    let removed = removed.unwrap().into_iter().filter(|key| !key.contains('_')).collect::<Vec<_>>();
+    assert_eq!(removed.len(), 2);
+    assert!(!state.uploads.dir.join(&keys[0]).exists());
+    assert!(state.uploads.dir.join(&keys[1]).exists());
+    assert!(state.uploads.dir.join(format!("{}_card.webp", &keys[1][..64])).exists());
+    assert!(!state.uploads.dir.join(format!("{}_card.webp", &keys[2][..64])).exists());
+
+    // Deleting the product drops its gallery, leaving the file for the next sweep
+    let resp = test::TestRequest::delete()
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let remaining = sqlx::query_scalar!("SELECT COUNT(*) FROM product_media").fetch_one(&state.db_pool).await.unwrap();
+    assert_eq!(remaining, 0);
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+#[actix_web::test]
+async fn test_files_are_listed_with_metadata_and_deleted_when_unused() {
+    let state = test_state().await;
+    let id = insert_product(&state, "Manual", 15.0).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    for (token, name, content) in [
+        (STAFF_TOKEN, "notes.txt", &b"first notes"[..]),
+        (ADMIN_TOKEN, "Manual.TXT", &b"the manual"[..]),
+        (STAFF_TOKEN, "more notes.txt", &b"second notes"[..]),
+    ] {
+        let (content_type, body) = multipart_body(&[(name, content)]);
+        let resp = test::TestRequest::post()
+            .uri("/api/upload")
+            .insert_header(bearer(token))
+            .insert_header(("Content-Type", content_type))
+            .set_payload(body)
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let list = |query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/api/files?{}", query))
+            .insert_header(bearer(STAFF_TOKEN))
+            .to_request()
+    };
+    let body: serde_json::Value = test::call_and_read_body_json(&app, list("limit=2")).await;
+    assert_eq!(body["total"], 3);
+    assert_eq!(body["files"].as_array().unwrap().len(), 2);
+    assert_eq!(body["files"][0]["original_name"], "more notes.txt");
+    assert_eq!(body["files"][0]["content_type"], "text/plain");
+    assert_eq!(body["files"][0]["size"], 12);
+    assert_eq!(body["files"][0]["sha256"], auth::hash_token("second notes"));
+    assert!(body["files"][0]["created_at"].as_i64().unwrap() > 0);
+    let body: serde_json::Value = test::call_and_read_body_json(&app, list("limit=2&offset=2")).await;
+    assert_eq!(body["files"][0]["original_name"], "notes.txt");
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, list("search=manual")).await;
+    assert_eq!(body["total"], 1);
+    let manual = body["files"][0].clone();
+    let admin = format!("token:{}", &auth::hash_token(ADMIN_TOKEN)[..12]);
+    assert_eq!(manual["owner"], admin);
+    let body: serde_json::Value = test::call_and_read_body_json(&app, list(&format!("owner={}", admin))).await;
+    assert_eq!(body["total"], 1);
+
+    // Only images can go in a product's gallery
+    let media_uri = format!("/api/products/{}/media", id);
+    let resp = test::TestRequest::post()
+        .uri(&media_uri)
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({ "file_key": manual["key"] }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // A file shown on a product cannot be deleted until it is detached
+    let (content_type, body) = multipart_body(&[("cover.png", &test_image(2, 2, image::ImageFormat::Png))]);
+    let resp = test::TestRequest::post()
+        .uri("/api/upload")
+        .insert_header(bearer(STAFF_TOKEN))
+        .insert_header(("Content-Type", content_type))
+        .set_payload(body)
+        .send_request(&app)
+        .await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let cover = body["files"][0].clone();
+    let resp = test::TestRequest::post()
+        .uri(&media_uri)
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({ "file_key": cover["key"] }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let media: serde_json::Value = test::read_body_json(resp).await;
+    let cover_uri = format!("/api/files/{}", cover["id"]);
+    let resp = test::TestRequest::delete().uri(&cover_uri).insert_header(bearer(ADMIN_TOKEN)).send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["product_ids"], serde_json::json!([id]));
+
+    test::TestRequest::delete()
+        .uri(&format!("{}/{}", media_uri, media["id"]))
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    let resp = test::TestRequest::delete().uri(&cover_uri).insert_header(bearer(ADMIN_TOKEN)).send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    // So is a file a product shows through an image URL set by hand
+    let file_uri = format!("/api/files/{}", manual["id"]);
+    let other = insert_product(&state, "Hand set", 12.0).await;
+    let image = format!("/api/download/{}?w=160", manual["key"].as_str().unwrap());
+    sqlx::query!("UPDATE products SET image = ? WHERE id = ?", image, other).execute(&state.db_pool).await.unwrap();
+    let resp = test::TestRequest::delete().uri(&file_uri).insert_header(bearer(ADMIN_TOKEN)).send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["product_ids"], serde_json::json!([other]));
+
+    // Or through one of its variants, which would go with it
+    let stem = manual["key"].as_str().unwrap().split('.').next().unwrap();
+    let variant = format!("/api/download/{}_card.webp", stem);
+    sqlx::query!("UPDATE products SET image = ? WHERE id = ?", variant, other).execute(&state.db_pool).await.unwrap();
+    let resp = test::TestRequest::delete().uri(&file_uri).insert_header(bearer(ADMIN_TOKEN)).send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    sqlx::query!("UPDATE products SET image = NULL WHERE id = ?", other).execute(&state.db_pool).await.unwrap();
+
+    let resp = test::TestRequest::delete().uri(&file_uri).insert_header(bearer(ADMIN_TOKEN)).send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert!(!state.uploads.dir.join(manual["key"].as_str().unwrap()).exists());
+    let resp = test::TestRequest::delete().uri(&file_uri).insert_header(bearer(ADMIN_TOKEN)).send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, list("")).await;
+    assert_eq!(body["total"], 2);
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+// Speaks just enough of clamd's INSTREAM protocol to flag anything containing "EICAR"
+fn spawn_fake_clamd() -> std::path::PathBuf {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let socket = std::env::temp_dir().join(format!("clamd-{}.sock", &auth::generate_token()[..16]));
+    let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+    actix_rt::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut command = [0u8; 10];
+            stream.read_exact(&mut command).await.unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+            let mut content = vec![];
+            loop {
+                let len = stream.read_u32().await.unwrap() as usize;
+                if len == 0 {
+                    break;
+                }
+                let mut chunk = vec![0u8; len];
+                stream.read_exact(&mut chunk).await.unwrap();
+                content.extend(chunk);
+            }
+            let infected = content.windows(5).any(|window| window == b"EICAR");
+            let reply: &[u8] = if infected { b"stream: Eicar-Test-Signature FOUND\0" } else { b"stream: OK\0" };
+            stream.write_all(reply).await.unwrap();
+        }
+    });
+    socket
+}
+
+#[actix_web::test]
+async fn test_uploads_are_quarantined_until_scanned() {
+    let socket = spawn_fake_clamd();
+    let clamd = scanner::ClamdScanner::new(scanner::ClamdAddress::Unix(socket.clone()));
+    let state = test_state_with_scanner(Box::new(clamd)).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let upload = |files: &[(&str, &[u8])]| {
+        let (content_type, body) = multipart_body(files);
+        test::TestRequest::post()
+            .uri("/api/upload")
+            .insert_header(bearer(STAFF_TOKEN))
+            .insert_header(("Content-Type", content_type))
+            .set_payload(body)
+            .to_request()
+    };
+
+    let resp = test::call_service(&app, upload(&[("fine.txt", b"nothing to see"), ("bad.txt", b"X5O!P%@AP EICAR test")])).await;
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["rejected"].as_array().unwrap().len(), 1);
+    assert_eq!(body["rejected"][0]["original_name"], "bad.txt");
+    assert_eq!(body["rejected"][0]["reason"], "Eicar-Test-Signature");
+
+    // Nothing from a rejected request leaves quarantine
+    let stored = std::fs::read_dir(&state.uploads.dir).unwrap().count();
+    assert_eq!(stored, 1);
+    assert_eq!(std::fs::read_dir(state.uploads.dir.join(".tmp")).unwrap().count(), 0);
+    let rejections = sqlx::query_scalar!("SELECT COUNT(*) FROM audit_log WHERE action = 'reject'")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(rejections, 1);
+
+    let resp = test::call_service(&app, upload(&[("fine.txt", b"nothing to see")])).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Presigned uploads are scanned when completed, and removed when rejected
+    let pending = state.uploads.dir.join("pending-eicar.txt");
+    std::fs::write(&pending, b"X5O!P%@AP EICAR test").unwrap();
+    let resp = test::TestRequest::post()
+        .uri("/api/upload/presigned/pending-eicar.txt/complete")
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(!pending.exists());
+
+    // An unreachable scanner fails closed
+    let _ = std::fs::remove_file(&socket);
+    let resp = test::call_service(&app, upload(&[("late.txt", b"arrives after clamd left")])).await;
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+#[actix_web::test]
+async fn test_resumable_uploads_survive_disconnects_and_expire() {
+    use sha2::{Digest, Sha256};
+
+    let state = test_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+    let content = "resumable ".repeat(250).into_bytes();
+
+    let resp = test::TestRequest::post()
+        .uri("/api/uploads")
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({ "filename": "specs/manual.txt", "size": content.len() }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let session: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(session["filename"], "manual.txt");
+    let uri = format!("/api/uploads/{}", session["id"].as_str().unwrap());
+
+    let put = |offset: usize, chunk: &[u8], checksum: String| {
+        test::TestRequest::put()
+            .uri(&uri)
+            .insert_header(bearer(STAFF_TOKEN))
+            .insert_header(("Upload-Offset", offset.to_string()))
+            .insert_header(("Upload-Checksum", format!("sha256 {}", checksum)))
+            .set_payload(chunk.to_vec())
+            .to_request()
+    };
+    let checksum = |chunk: &[u8]| hex::encode(Sha256::digest(chunk));
+    let offset = |app_uri: &str| {
+        test::TestRequest::get().uri(app_uri).insert_header(bearer(STAFF_TOKEN)).to_request()
+    };
+
+    // A corrupted chunk is discarded, so the offset does not move
+    let resp = test::call_service(&app, put(0, &content[..1000], checksum(b"something else"))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, put(0, &content[..1000], checksum(&content[..1000]))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "1000");
+
+    // After a disconnect the client asks where to continue
+    let session: serde_json::Value = test::call_and_read_body_json(&app, offset(&uri)).await;
+    assert_eq!(session["offset"], 1000);
+    let resp = test::call_service(&app, put(0, &content[..1000], checksum(&content[..1000]))).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = test::call_service(&app, put(1000, &content[1000..2100], checksum(&content[1000..2100]))).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let resp = test::call_service(&app, put(1000, &content[1000..2000], checksum(&content[1000..2000]))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let complete = |sha256: String| {
+        test::TestRequest::post()
+            .uri(&format!("{}/complete", uri))
+            .insert_header(bearer(STAFF_TOKEN))
+            .set_json(serde_json::json!({ "sha256": sha256 }))
+            .to_request()
+    };
+    let resp = test::call_service(&app, complete(checksum(&content))).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = test::call_service(&app, put(2000, &content[2000..], checksum(&content[2000..]))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Sessions are private to whoever started them
+    let resp = test::TestRequest::get().uri(&uri).insert_header(bearer(ADMIN_TOKEN)).send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = test::call_service(&app, complete(checksum(&content))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let file = &body["files"][0];
+    assert_eq!(file["sha256"], checksum(&content));
+    assert_eq!(file["size"], content.len());
+    assert_eq!(std::fs::read(state.uploads.dir.join(file["key"].as_str().unwrap())).unwrap(), content);
+    let resp = test::call_service(&app, offset(&uri)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Images are decoded in memory, so they stay within the per-file limit however they arrive
+    let mut image = test_image(2, 2, image::ImageFormat::Png);
+    image.resize(3000, 0);
+    let resp = test::TestRequest::post()
+        .uri("/api/uploads")
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({ "filename": "poster.png", "size": image.len() }))
+        .send_request(&app)
+        .await;
+    let session: serde_json::Value = test::read_body_json(resp).await;
+    let image_uri = format!("/api/uploads/{}", session["id"].as_str().unwrap());
+    for (index, chunk) in image.chunks(1000).enumerate() {
+        let resp = test::TestRequest::put()
+            .uri(&image_uri)
+            .insert_header(bearer(STAFF_TOKEN))
+            .insert_header(("Upload-Offset", (index * 1000).to_string()))
+            .insert_header(("Upload-Checksum", format!("sha256 {}", checksum(chunk))))
+            .set_payload(chunk.to_vec())
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let resp = test::TestRequest::post()
+        .uri(&format!("{}/complete", image_uri))
+        .insert_header(bearer(STAFF_TOKEN))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let resp = test::call_service(&app, offset(&image_uri)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Abandoned sessions are swept once they expire
+    let resp = test::TestRequest::post()
+        .uri("/api/uploads")
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({ "filename": "video.mp4", "size": 5000 }))
+        .send_request(&app)
+        .await;
+    let session: serde_json::Value = test::read_body_json(resp).await;
+    let id = session["id"].as_str().unwrap().to_string();
+    assert_eq!(resumable::remove_expired_sessions(&state).await.unwrap(), 0);
+    sqlx::query!("UPDATE upload_sessions SET expires_at = 0 WHERE id = ?", id).execute(&state.db_pool).await.unwrap();
+    assert_eq!(resumable::remove_expired_sessions(&state).await.unwrap(), 1);
+    assert_eq!(std::fs::read_dir(state.uploads.dir.join(".tmp")).unwrap().count(), 0);
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+#[actix_web::test]
+async fn test_upload_limits_are_all_or_nothing() {
+    let state = test_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+    let upload = |files: &[(&str, &[u8])]| {
+        let (content_type, body) = multipart_body(files);
+        test::TestRequest::post()
+            .uri("/api/upload")
+            .insert_header(bearer(STAFF_TOKEN))
+            .insert_header(("Content-Type", content_type))
+            .set_payload(body)
+            .to_request()
+    };
+    let small = [b'a'; 600];
+    let large = [b'b'; 1500];
+
+    let resp = test::call_service(&app, upload(&[("one.txt", b"first file"), ("two.txt", b"second file")])).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let files = body["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[1]["original_name"], "two.txt");
+    assert_eq!(files[1]["size"], 11);
+    assert_eq!(files[1]["sha256"], auth::hash_token("second file"));
+
+    // A file over the per-file limit fails the request, including the files before it
+    let resp = test::call_service(&app, upload(&[("ok.txt", &small), ("big.txt", &large)])).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["error"].as_str().unwrap().contains("big.txt"));
+
+    // Files under the per-file limit can still add up past the per-request limit
+    let many = [b'c'; 900];
+    let resp = test::call_service(&app, upload(&[("a.txt", &many), ("b.txt", &many), ("c.txt", &many), ("d.txt", &many), ("e.txt", &many)])).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Plain form fields are not files, so they are refused rather than stored as "unnamed"
+    let (content_type, mut body) = multipart_body(&[("ok.txt", b"fine")]);
+    let field = b"--test-boundary\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhello\r\n";
+    body.splice(0..0, field.iter().copied());
+    let resp = test::TestRequest::post()
+        .uri("/api/upload")
+        .insert_header(bearer(STAFF_TOKEN))
+        .insert_header(("Content-Type", content_type))
+        .set_payload(body)
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["error"].as_str().unwrap().contains("note"));
+
+    let stored = std::fs::read_dir(&state.uploads.dir).unwrap().count();
+    assert_eq!(stored, 3, "only the first request's two files and the temp dir should remain");
+    assert_eq!(std::fs::read_dir(state.uploads.dir.join(".tmp")).unwrap().count(), 0);
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+// Passes everything through to the filesystem, except that its `fail_on`th put errors
+struct FailingStore {
+    inner: storage::FileSystemStore,
+    puts: std::sync::atomic::AtomicUsize,
+    fail_on: usize,
+}
+
+#[async_trait::async_trait]
+impl storage::BlobStore for FailingStore {
+    async fn put_file(&self, key: &str, source: &std::path::Path, content_type: &str) -> std::io::Result<()> {
+        if self.puts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1 == self.fail_on {
+            return Err(std::io::Error::other("disk full"));
+        }
+        self.inner.put_file(key, source, content_type).await
+    }
+
+    async fn open(&self, key: &str) -> std::io::Result<Option<storage::BlobObject>> {
+        self.inner.open(key).await
+    }
+
+    async fn exists(&self, key: &str) -> std::io::Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn list(&self) -> std::io::Result<Vec<storage::BlobInfo>> {
+        self.inner.list().await
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn check(&self) -> std::io::Result<()> {
+        self.inner.check().await
+    }
+}
+
+#[actix_web::test]
+async fn test_failed_store_rolls_back_the_whole_upload() {
+    let state = test_state().await;
+    let mut state = std::sync::Arc::try_unwrap(state.into_inner()).ok().unwrap();
+    state.blobs = Box::new(FailingStore {
+        inner: storage::FileSystemStore::new(state.uploads.dir.clone()),
+        puts: Default::default(),
+        fail_on: 4,
+    });
+    let state = web::Data::new(state);
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+    let upload = |files: &[(&str, &[u8])]| {
+        let (content_type, body) = multipart_body(files);
+        test::TestRequest::post()
+            .uri("/api/upload")
+            .insert_header(bearer(STAFF_TOKEN))
+            .insert_header(("Content-Type", content_type))
+            .set_payload(body)
+            .to_request()
+    };
+    let existing = format!("{}.txt", auth::hash_token("first file"));
+    let added = format!("{}.txt", auth::hash_token("second file"));
+
+    let resp = test::call_service(&app, upload(&[("one.txt", b"first file")])).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // The third file's put fails after the first two went in
+    let resp = test::call_service(
+        &app,
+        upload(&[("one.txt", b"first file"), ("two.txt", b"second file"), ("three.txt", b"third file")]),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    // What the batch added is gone again, while the file stored earlier keeps its object and row
+    assert!(state.blobs.exists(&existing).await.unwrap());
+    assert!(!state.blobs.exists(&added).await.unwrap());
+    let keys = sqlx::query_scalar!("SELECT key FROM files").fetch_all(&state.db_pool).await.unwrap();
+    assert_eq!(keys, vec![existing]);
+    let uploads = sqlx::query_scalar!("SELECT COUNT(*) FROM audit_log WHERE action = 'upload'")
+        .fetch_one(&state.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(uploads, 1);
+    assert_eq!(state.metrics.upload_bytes.get(), 10);
+
+    let resp = test::call_service(&app, upload(&[("two.txt", b"second file")])).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(state.uploads.dir.join(&added).exists());
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+#[actix_web::test]
+async fn test_downloads_are_confined_and_support_ranges() {
+    let state = test_state().await;
+    std::fs::create_dir_all(state.uploads.dir.join(".tmp")).unwrap();
+    std::fs::write(state.uploads.dir.join("notes.txt"), b"hello, range requests").unwrap();
+    std::fs::write(state.uploads.dir.join(".tmp").join("partial"), b"in progress").unwrap();
+    let outside = state.uploads.dir.with_extension("outside");
+    std::fs::write(&outside, b"secret").unwrap();
+    std::os::unix::fs::symlink(&outside, state.uploads.dir.join("link.txt")).unwrap();
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let resp = test::TestRequest::get().uri("/api/download/notes.txt").send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("Content-Type").unwrap().to_str().unwrap().starts_with("text/plain"));
+    assert_eq!(resp.headers().get("Accept-Ranges").unwrap(), "bytes");
+    assert!(resp.headers().contains_key("Last-Modified"));
+    let etag = resp.headers().get("ETag").unwrap().clone();
+
+    let resp = test::TestRequest::get()
+        .uri("/api/download/notes.txt")
+        .insert_header(("Range", "bytes=7-11"))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resp.headers().get("Content-Range").unwrap(), "bytes 7-11/21");
+    assert_eq!(test::read_body(resp).await, "range");
+
+    let resp = test::TestRequest::get()
+        .uri("/api/download/notes.txt")
+        .insert_header(("If-None-Match", etag))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    for uri in [
+        "/api/download/..%2Foutside.txt",
+        "/api/download/%2E%2E%2Foutside.txt",
+        "/api/download/.tmp%2Fpartial",
+        "/api/download/link.txt",
+        "/api/download/missing.txt",
+    ] {
+        let resp = test::TestRequest::get().uri(uri).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", uri);
+    }
+
+    let _ = std::fs::remove_file(&outside);
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+// Behaves like a remote object store: objects come back as streams, and uploads can be presigned
+struct RemoteStore {
+    inner: storage::FileSystemStore,
+}
+
+#[async_trait::async_trait]
+impl storage::BlobStore for RemoteStore {
+    async fn put_file(&self, key: &str, source: &std::path::Path, content_type: &str) -> std::io::Result<()> {
+        self.inner.put_file(key, source, content_type).await
+    }
+
+    async fn open(&self, key: &str) -> std::io::Result<Option<storage::BlobObject>> {
+        self.open_range(key, "bytes=0-").await
+    }
+
+    // Understands `bytes=<start>-` and `bytes=<start>-<end>`, which is all the tests ask for
+    async fn open_range(&self, key: &str, range: &str) -> std::io::Result<Option<storage::BlobObject>> {
+        let Some(storage::BlobObject::File(path)) = self.inner.open(key).await? else {
+            return Ok(None);
+        };
+        let bytes = std::fs::read(path)?;
+        let total = bytes.len() as u64;
+        let (start, end) = range.strip_prefix("bytes=").and_then(|range| range.split_once('-')).unwrap();
+        let start: u64 = start.parse().unwrap();
+        let end = end.parse().map_or(total.saturating_sub(1), |end: u64| end.min(total - 1));
+        if start >= total && total > 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "range not satisfiable"));
+        }
+        let partial = start > 0 || end + 1 < total;
+        let body = bytes[start as usize..=end as usize].to_vec();
+        Ok(Some(storage::BlobObject::Stream {
+            content_type: Some("text/plain".to_string()),
+            size: Some(body.len() as u64),
+            etag: Some(format!("\"{}\"", auth::hash_token(key))),
+            content_range: partial.then(|| format!("bytes {}-{}/{}", start, end, total)),
+            body: Box::pin(futures::stream::iter([Ok(web::Bytes::from(body))])),
+        }))
+    }
+
+    async fn exists(&self, key: &str) -> std::io::Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn list(&self) -> std::io::Result<Vec<storage::BlobInfo>> {
+        self.inner.list().await
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn check(&self) -> std::io::Result<()> {
+        self.inner.check().await
+    }
+
+    async fn presign_upload(
+        &self,
+        key: &str,
+        _content_type: &str,
+        _size: u64,
+    ) -> std::io::Result<Option<storage::PresignedRequest>> {
+        Ok(Some(storage::PresignedRequest {
+            method: "PUT".to_string(),
+            url: format!("https://objects.test/{}", key),
+            headers: Default::default(),
+        }))
+    }
+}
+
+async fn remote_state() -> web::Data<AppState> {
+    let state = test_state().await;
+    let mut state = std::sync::Arc::try_unwrap(state.into_inner()).ok().unwrap();
+    state.blobs = Box::new(RemoteStore { inner: storage::FileSystemStore::new(state.uploads.dir.clone()) });
+    web::Data::new(state)
+}
+
+#[actix_web::test]
+async fn test_presigned_uploads_are_recorded_once_completed() {
+    let state = remote_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+    // Stands in for the client's PUT to the presigned URL
+    let put = |key: &str, content: &[u8]| {
+        let source = std::env::temp_dir().join(format!("presigned-{}", auth::generate_token()));
+        std::fs::write(&source, content).unwrap();
+        let state = state.clone();
+        let key = key.to_string();
+        async move { state.blobs.put_file(&key, &source, "application/octet-stream").await.unwrap() }
+    };
+    let presign = |size: usize| {
+        test::TestRequest::post()
+            .uri("/api/upload/presigned")
+            .insert_header(bearer(STAFF_TOKEN))
+            .set_json(serde_json::json!({ "content_type": "image/png", "size": size }))
+            .to_request()
+    };
+    let complete = |key: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/api/upload/presigned/{}/complete", key))
+            .insert_header(bearer(STAFF_TOKEN))
+            .set_json(serde_json::json!({ "original_name": "photo.png" }))
+            .to_request()
+    };
+    let listed = || test::TestRequest::get().uri("/api/files").insert_header(bearer(STAFF_TOKEN)).to_request();
+
+    let png = test_image(2, 2, image::ImageFormat::Png);
+    let body: serde_json::Value = test::call_and_read_body_json(&app, presign(png.len())).await;
+    let key = body["key"].as_str().unwrap().to_string();
+    assert!(key.starts_with("pending-") && key.ends_with(".png"));
+    put(&key, &png).await;
+
+    // Until completed the object can be neither downloaded nor listed
+    let resp = test::TestRequest::get().uri(&format!("/api/download/{}", key)).send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = test::call_and_read_body_json(&app, listed()).await;
+    assert_eq!(body["total"], 0);
+
+    let resp = test::call_service(&app, complete(&key)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let stored = &body["files"][0];
+    assert_eq!(stored["original_name"], "photo.png");
+    assert_eq!(stored["content_type"], "image/png");
+    assert_eq!(stored["key"], format!("{}.png", stored["sha256"].as_str().unwrap()));
+    assert!(state.blobs.open(&key).await.unwrap().is_none());
+    let body: serde_json::Value = test::call_and_read_body_json(&app, listed()).await;
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["files"][0]["id"], stored["id"]);
+
+    let resp = test::call_service(&app, complete(&key)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Content is checked as for any other upload, whatever the presigned request claimed
+    let body: serde_json::Value = test::call_and_read_body_json(&app, presign(64)).await;
+    let key = body["key"].as_str().unwrap().to_string();
+    put(&key, b"MZ\x90\0\x03\0\0\0\x04\0\0\0\xff\xff").await;
+    let resp = test::call_service(&app, complete(&key)).await;
+    assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(state.blobs.open(&key).await.unwrap().is_none());
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, presign(64)).await;
+    let key = body["key"].as_str().unwrap().to_string();
+    put(&key, &[b'a'; 2048]).await;
+    let resp = test::call_service(&app, complete(&key)).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(state.blobs.open(&key).await.unwrap().is_none());
+
+    // Uploads nobody completes are swept like unattached files
+    let body: serde_json::Value = test::call_and_read_body_json(&app, presign(png.len())).await;
+    let abandoned = body["key"].as_str().unwrap().to_string();
+    put(&abandoned, &png).await;
+    let removed = media::remove_orphaned_files(&state, "test", Duration::from_secs(3600)).await.unwrap();
+    assert!(!removed.contains(&abandoned));
+    let removed = media::remove_orphaned_files(&state, "test", Duration::ZERO).await.unwrap();
+    assert!(removed.contains(&abandoned));
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+#[actix_web::test]
+async fn test_streamed_downloads_support_ranges_and_validators() {
+    let state = remote_state().await;
+    std::fs::create_dir_all(&state.uploads.dir).unwrap();
+    std::fs::write(state.uploads.dir.join("notes.txt"), b"hello, range requests").unwrap();
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let resp = test::TestRequest::get().uri("/api/download/notes.txt").send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("Accept-Ranges").unwrap(), "bytes");
+    let etag = resp.headers().get("ETag").unwrap().clone();
+    assert_eq!(test::read_body(resp).await, "hello, range requests");
+
+    let resp = test::TestRequest::get()
+        .uri("/api/download/notes.txt")
+        .insert_header(("Range", "bytes=7-11"))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resp.headers().get("Content-Range").unwrap(), "bytes 7-11/21");
+    assert_eq!(resp.headers().get("Content-Length").unwrap(), "5");
+    assert_eq!(test::read_body(resp).await, "range");
+
+    let resp = test::TestRequest::get()
+        .uri("/api/download/notes.txt")
+        .insert_header(("Range", "bytes=100-"))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+    // Ranges the backends may not agree on are ignored rather than forwarded
+    let resp = test::TestRequest::get()
+        .uri("/api/download/notes.txt")
+        .insert_header(("Range", "bytes=0-1,4-5"))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    for if_none_match in [etag.to_str().unwrap().to_string(), format!("W/{}", etag.to_str().unwrap()), "*".to_string()] {
+        let resp = test::TestRequest::get()
+            .uri("/api/download/notes.txt")
+            .insert_header(("If-None-Match", if_none_match))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers().get("ETag").unwrap(), etag);
+    }
+    let resp = test::TestRequest::get()
+        .uri("/api/download/notes.txt")
+        .insert_header(("If-None-Match", "\"something-else\""))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
+
+fn s3_test_config(endpoint: &str) -> storage::S3Config {
+    storage::S3Config {
+        bucket: std::env::var("S3_TEST_BUCKET").unwrap_or_else(|_| "uploads-test".to_string()),
+        endpoint: Some(endpoint.to_string()),
+        region: "us-east-1".to_string(),
+        access_key_id: std::env::var("S3_TEST_ACCESS_KEY_ID").unwrap_or_else(|_| "minioadmin".to_string()),
+        secret_access_key: std::env::var("S3_TEST_SECRET_ACCESS_KEY").unwrap_or_else(|_| "minioadmin".to_string()),
+    }
+}
+
+#[actix_web::test]
+async fn test_presigned_urls_depend_on_the_backend() {
+    use storage::BlobStore;
+
+    let state = test_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+    let resp = test::TestRequest::post()
+        .uri("/api/upload/presigned")
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({ "content_type": "image/png", "size": 100 }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_IMPLEMENTED);
+
+    // Signing happens locally, so no server is needed to check the URL shape
+    let store = storage::S3Store::new(s3_test_config("http://127.0.0.1:9000"));
+    let upload = store.presign_upload("abc.png", "image/png", 100).await.unwrap().unwrap();
+    assert_eq!(upload.method, "PUT");
+    assert!(upload.url.starts_with("http://127.0.0.1:9000/uploads-test/abc.png?"));
+    assert!(upload.url.contains("X-Amz-Signature="));
+    assert!(upload.url.contains("X-Amz-Expires=900"));
+    assert_eq!(upload.headers.get("content-type").map(String::as_str), Some("image/png"));
+
+    let download = store.presign_download("abc.png", "abc.png").await.unwrap().unwrap();
+    assert_eq!(download.method, "GET");
+    assert!(download.url.contains("response-content-disposition="));
+}
+
+// Needs a real S3 endpoint, so it only runs when asked for. Against a local MinIO:
+//   docker run -p 9000:9000 minio/minio server /data
+//   (create a bucket named `uploads-test`, or set S3_TEST_BUCKET)
+//   S3_TEST_ENDPOINT=http://127.0.0.1:9000 cargo test s3_store -- --ignored
+#[actix_web::test]
+#[ignore = "needs an S3 endpoint in S3_TEST_ENDPOINT"]
+async fn test_s3_store_round_trip() {
+    use storage::{BlobObject, BlobStore};
+
+    let endpoint = std::env::var("S3_TEST_ENDPOINT").expect("S3_TEST_ENDPOINT must point at an S3 endpoint");
+    let store = storage::S3Store::new(s3_test_config(&endpoint));
+    let source = std::env::temp_dir().join(format!("s3-source-{}", auth::generate_token()));
+    std::fs::write(&source, b"stored in a bucket").unwrap();
+    let key = format!("{}.txt", auth::generate_token());
+
+    store.check().await.unwrap();
+    assert!(!store.exists(&key).await.unwrap());
+    store.put_file(&key, &source, "text/plain").await.unwrap();
+    assert!(store.exists(&key).await.unwrap());
+    assert!(store.list().await.unwrap().iter().any(|file| file.key == key));
+    match store.open(&key).await.unwrap() {
+        Some(BlobObject::Stream { size, body, .. }) => {
+            assert_eq!(size, Some(18));
+            let chunks = body.collect::<Vec<_>>().await;
+            let content = chunks.into_iter().map(Result::unwrap).collect::<Vec<_>>().concat();
+            assert_eq!(content, b"stored in a bucket");
+        }
+        _ => panic!("expected a streamed object"),
+    }
+    match store.open_range(&key, "bytes=10-17").await.unwrap() {
+        Some(BlobObject::Stream { content_range, body, .. }) => {
+            assert_eq!(content_range.as_deref(), Some("bytes 10-17/18"));
+            let chunks = body.collect::<Vec<_>>().await;
+            let content = chunks.into_iter().map(Result::unwrap).collect::<Vec<_>>().concat();
+            assert_eq!(content, b"a bucket");
+        }
+        _ => panic!("expected a streamed object"),
+    }
+    let unsatisfiable = store.open_range(&key, "bytes=100-").await.err().unwrap();
+    assert_eq!(unsatisfiable.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(store.open("missing.txt").await.unwrap().is_none());
+
+    store.delete(&key).await.unwrap();
+    assert!(store.open(&key).await.unwrap().is_none());
+    let _ = std::fs::remove_file(&source);
+}
+
+#[actix_web::test]
+async fn test_requests_carry_an_id_and_log_levels_can_change() {
+    let state = test_state().await;
+    let app = test::init_service(
+        App::new()
+            .wrap(telemetry::RequestTracing)
+            .app_data(state.clone())
+            .configure(configure_routes),
+    )
+    .await;
+
+    // A usable id from the caller is echoed back
+    let resp = test::TestRequest::get()
+        .uri("/api/health")
+        .insert_header(("X-Request-Id", "trace-abc-123"))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.headers().get("x-request-id").unwrap(), "trace-abc-123");
+
+    // Otherwise one is generated, and error bodies mention it
+    let resp = test::TestRequest::get()
+        .uri("/api/admin/log-level")
+        .insert_header(("X-Request-Id", "has spaces"))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let id = resp.headers().get("x-request-id").unwrap().to_str().unwrap().to_string();
+    assert_eq!(id.len(), 32);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["error"].is_string());
+    assert_eq!(body["request_id"], id);
+
+    let resp = test::TestRequest::put()
+        .uri("/api/admin/log-level")
+        .insert_header(bearer(ADMIN_TOKEN))
+        .set_json(serde_json::json!({ "filter": "warn,backend::realtime=debug" }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::TestRequest::put()
+        .uri("/api/admin/log-level")
+        .insert_header(bearer(ADMIN_TOKEN))
+        .set_json(serde_json::json!({ "filter": "backend=loudest" }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = test::TestRequest::get()
+        .uri("/api/admin/log-level")
+        .insert_header(bearer(ADMIN_TOKEN))
+        .send_request(&app)
+        .await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["filter"], "warn,backend::realtime=debug");
+}
+
+#[actix_web::test]
+async fn test_metrics_expose_requests_and_pool_usage() {
+    let state = test_state().await;
+    let app = test::init_service(
+        App::new()
+            .wrap(telemetry::RequestTracing)
+            .app_data(state.clone())
+            .configure(configure_routes),
+    )
+    .await;
+
+    for uri in ["/api/health", "/api/health", "/api/products/123/nope"] {
+        test::TestRequest::get().uri(uri).send_request(&app).await;
+    }
+    state.metrics.generator_products.with_label_values(&["generate"]).inc_by(3);
+
+    let resp = test::TestRequest::get().uri("/metrics").send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("content-type").unwrap().to_str().unwrap().starts_with("text/plain"));
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+
+    assert!(body.contains(r#"http_requests_total{method="GET",route="/api/health",status="200"} 2"#));
+    assert!(body.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+    assert!(body.contains(r#"http_request_duration_seconds_count{method="GET",route="/api/health",status="200"} 2"#));
+    assert!(body.contains("db_pool_max_connections 1"));
+    assert!(body.contains(r#"db_pool_connections{state="idle"}"#));
+    assert!(body.contains("websocket_connections 0"));
+    assert!(body.contains(r#"generator_products_total{mode="generate"} 3"#));
+    assert!(body.contains("upload_bytes_total 0"));
+}
+
+#[actix_web::test]
+async fn test_request_spans_join_the_callers_trace() {
+    use opentelemetry::trace::{SpanKind, TracerProvider};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+    let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let state = test_state().await;
+    let app = test::init_service(
+        App::new()
+            .wrap(telemetry::RequestTracing)
+            .app_data(state.clone())
+            .configure(configure_routes),
+    )
+    .await;
+    let resp = test::TestRequest::get()
+        .uri("/api/products")
+        .insert_header(("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    drop(resp);
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let request = spans.iter().find(|span| span.name == "GET /api/products").expect("request span");
+    assert_eq!(request.span_kind, SpanKind::Server);
+    assert_eq!(request.span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(request.parent_span_id.to_string(), "00f067aa0ba902b7");
+
+    // The query shows up as its own child span, separate from the handler's own work
+    let query = spans.iter().find(|span| span.name == "SELECT products").expect("query span");
+    assert_eq!(query.span_kind, SpanKind::Client);
+    assert_eq!(query.parent_span_id, request.span_context.span_id());
+    assert!(spans.iter().any(|span| span.name == "filter_and_sort_products"));
+
+    // Looking up the caller's session is traced too, even when it turns them away
+    let resp = test::TestRequest::get()
+        .uri("/api/files")
+        .insert_header(bearer("not-a-session"))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    drop(resp);
+    let spans = exporter.get_finished_spans().unwrap();
+    let request = spans.iter().find(|span| span.name == "GET /api/files").expect("request span");
+    let lookup = spans.iter().find(|span| span.name == "SELECT sessions").expect("session lookup span");
+    assert_eq!(lookup.parent_span_id, request.span_context.span_id());
+}
+
+#[actix_web::test]
+async fn test_readiness_reports_each_dependency() {
+    let state = test_state().await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let resp = test::TestRequest::get().uri("/health/ready").send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["status"], "ok");
+    for check in ["database", "migrations", "storage", "generator"] {
+        assert_eq!(body["checks"][check]["status"], "ok", "{}", check);
+        assert!(body["checks"][check]["latency_ms"].is_u64());
+    }
+
+    // An upload directory that cannot be created degrades storage only
+    std::fs::remove_dir_all(&state.uploads.dir).unwrap();
+    std::fs::write(&state.uploads.dir, b"not a directory").unwrap();
+    let resp = test::TestRequest::get().uri("/health/ready").send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["checks"]["storage"]["status"], "degraded");
+    assert!(body["checks"]["storage"]["error"].is_string());
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    std::fs::remove_file(&state.uploads.dir).unwrap();
+
+    state.db_pool.close().await;
+    let resp = test::TestRequest::get().uri("/health/ready").send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["checks"]["database"]["status"], "degraded");
+    assert_eq!(body["checks"]["migrations"]["status"], "degraded");
+    assert_eq!(body["checks"]["storage"]["status"], "ok");
+
+    // Liveness does not depend on any of them
+    let resp = test::TestRequest::get().uri("/health/live").send_request(&app).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_orphan_cleanup_keeps_hand_set_images_and_unrecorded_uploads() {
+    let state = test_state().await;
+    let id = insert_product(&state, "Hand set", 25.0).await;
+    let app = test::init_service(App::new().app_data(state.clone()).configure(configure_routes)).await;
+
+    let (content_type, body) = multipart_body(&[("photo.png", &test_image(2, 2, image::ImageFormat::Png))]);
+    let resp = test::TestRequest::post()
+        .uri("/api/upload")
+        .insert_header(bearer(STAFF_TOKEN))
+        .insert_header(("Content-Type", content_type))
+        .set_payload(body)
+        .send_request(&app)
+        .await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let key = body["files"][0]["key"].as_str().unwrap().to_string();
+
+    // The image is set through the product itself, not through media
+    let resp = test::TestRequest::put()
+        .uri(&format!("/api/products/{}", id))
+        .insert_header(bearer(STAFF_TOKEN))
+        .set_json(serde_json::json!({
+            "id": id,
+            "name": "Hand set",
+            "price": 25.0,
+            "image": format!("/api/download/{}", key),
+            "description": "Image set by hand",
+            "category": "Test"
+        }))
+        .send_request(&app)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // An upload from before files were recorded
+    let legacy = "9b0fc295-legacy.jpeg";
+    std::fs::write(state.uploads.dir.join(legacy), b"legacy").unwrap();
+
+    let removed = media::remove_orphaned_files(&state, "test", Duration::ZERO).await.unwrap();
+    assert!(removed.is_empty(), "{:?}", removed);
+    assert!(state.uploads.dir.join(&key).exists());
+    assert!(state.uploads.dir.join(legacy).exists());
+
+    let _ = std::fs::remove_dir_all(&state.uploads.dir);
+}
@@ -1,4 +1,4 @@
-use crate::models::CreateProductRequest;
+use crate::models::{CreateProductRequest, RegisterRequest, GeneratorConfig};
 use actix_web::HttpResponse;
 
 pub struct ValidationError {
@@ -37,4 +37,56 @@ pub fn validate_product(product: &CreateProductRequest) -> Result<(), Validation
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn validate_registration(request: &RegisterRequest) -> Result<(), ValidationError> {
+    let email = request.email.trim();
+    let valid_email = match email.split_once('@') {
+        Some((local, domain)) => !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.'),
+        None => false,
+    };
+    if !valid_email {
+        return Err(ValidationError::new("Email address is not valid".to_string()));
+    }
+
+    if request.password.len() < 8 {
+        return Err(ValidationError::new("Password must be at least 8 characters long".to_string()));
+    }
+
+    Ok(())
+}
+
+pub fn validate_generator_config(config: &GeneratorConfig) -> Result<(), ValidationError> {
+    if config.interval_ms < 100 {
+        return Err(ValidationError::new("Interval must be at least 100 milliseconds".to_string()));
+    }
+
+    if config.batch_size == 0 || config.batch_size > 100 {
+        return Err(ValidationError::new("Batch size must be between 1 and 100".to_string()));
+    }
+
+    if !(config.volatility > 0.0 && config.volatility <= 50.0) {
+        return Err(ValidationError::new("Volatility must be greater than 0 and at most 50 percent".to_string()));
+    }
+
+    if let Some(mix) = &config.category_mix {
+        if mix.is_empty() || mix.values().all(|weight| *weight == 0) || mix.keys().any(|c| c.trim().is_empty()) {
+            return Err(ValidationError::new("Category mix needs at least one named category with a positive weight".to_string()));
+        }
+    }
+
+    if config.max_count == Some(0) {
+        return Err(ValidationError::new("Maximum count must be greater than 0".to_string()));
+    }
+
+    Ok(())
+}
+
+// Alt text is read aloud by screen readers, so it should stay a short description
+pub fn validate_alt_text(alt_text: Option<&str>) -> Result<(), ValidationError> {
+    if alt_text.is_some_and(|text| text.chars().count() > 300) {
+        return Err(ValidationError::new("Alt text must be at most 300 characters long".to_string()));
+    }
+
+    Ok(())
+}
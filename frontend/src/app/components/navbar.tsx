@@ -3,6 +3,7 @@
 import Link from "next/link";
 import { useState } from "react";
 import { useRouter } from "next/navigation";
+import { LoginPanel } from "../../components/LoginPanel";
 
 export default function Navbar() {
   return (
@@ -10,12 +11,15 @@ export default function Navbar() {
       <Link href="/" className="text-xl font-bold">
         BobShop
       </Link>
-      <Link
-        href="/admin-view"
-        className="px-4 py-2 bg-white text-blue-500 font-semibold rounded shadow hover:bg-gray-100"
-      >
-        Admin Panel
-      </Link>
+      <div className="flex items-center gap-4">
+        <LoginPanel />
+        <Link
+          href="/admin-view"
+          className="px-4 py-2 bg-white text-blue-500 font-semibold rounded shadow hover:bg-gray-100"
+        >
+          Admin Panel
+        </Link>
+      </div>
     </nav>
   );
 }
@@ -10,7 +10,10 @@ import StatusIndicator from "../components/StatusIndicator";
 import { FileManager } from "../components/FileManager";
 import Toast from "./components/Toast";
 import { ProductFiltersComponent } from "./components/ProductFilters";
-import WebSocketService from "../services/websocketService";
+import WebSocketService, {
+  ProductEvent,
+} from "../services/websocketService";
+import AuthService from "../services/authService";
 
 export default function Home() {
   const [products, setProducts] = useState<Product[]>([]);
@@ -198,15 +201,50 @@ export default function Home() {
     const wsService = WebSocketService.getInstance();
     wsService.connect();
 
-    const handleNewProduct = (product: Product) => {
-      setProducts((prevProducts) => [...prevProducts, product]);
-      showNotification(`New product added: ${product.name}`);
+    const handleProductEvent = (event: ProductEvent) => {
+      switch (event.type) {
+        case "product_created":
+          setProducts((prevProducts) => [...prevProducts, event.product]);
+          showNotification(`New product added: ${event.product.name}`);
+          break;
+        case "product_updated":
+          setProducts((prevProducts) =>
+            prevProducts.map((p) =>
+              p.id === event.product.id ? event.product : p
+            )
+          );
+          break;
+        case "product_deleted":
+          setProducts((prevProducts) =>
+            prevProducts.filter((p) => p.id !== event.product.id)
+          );
+          break;
+        case "price_changed":
+          setProducts((prevProducts) =>
+            prevProducts.map((p) =>
+              p.id === event.change.product_id
+                ? { ...p, price: event.change.new_price }
+                : p
+            )
+          );
+          break;
+        case "snapshot": {
+          // Refresh the loaded page without pulling in the whole catalog
+          const current = new Map(event.products.map((p) => [p.id, p]));
+          setProducts((prevProducts) =>
+            prevProducts
+              .filter((p) => current.has(p.id))
+              .map((p) => current.get(p.id) as Product)
+          );
+          break;
+        }
+      }
     };
 
-    wsService.addListener(handleNewProduct);
+    wsService.addListener(handleProductEvent);
 
     return () => {
-      wsService.removeListener(handleNewProduct);
+      wsService.removeListener(handleProductEvent);
       wsService.disconnect();
     };
   }, []);
@@ -220,6 +258,7 @@ export default function Home() {
           method: "POST",
           headers: {
             "Content-Type": "application/json",
+            ...AuthService.getInstance().authHeaders(),
           },
           body: JSON.stringify(!isGenerating),
         }
@@ -230,6 +269,9 @@ export default function Home() {
         showNotification(
           `Product generation ${!isGenerating ? "started" : "stopped"}`
         );
+      } else {
+        const errorData = await response.json();
+        setError(errorData.error || "Failed to toggle product generation");
       }
     } catch (error) {
       console.error("Error toggling product generation:", error);
@@ -1,5 +1,5 @@
 import React, { useState, useCallback, useEffect } from "react";
-import { FileService } from "../services/fileService";
+import { FileRecord, FileService } from "../services/fileService";
 import { useOffline } from "../hooks/useOffline";
 
 const fileService = FileService.getInstance();
@@ -8,7 +8,7 @@ export const FileManager: React.FC = () => {
   const [selectedFile, setSelectedFile] = useState<File | null>(null);
   const [uploading, setUploading] = useState(false);
   const [error, setError] = useState<string | null>(null);
-  const [files, setFiles] = useState<string[]>([]);
+  const [files, setFiles] = useState<FileRecord[]>([]);
   const [loading, setLoading] = useState(true);
   const { isOffline } = useOffline();
 
@@ -21,8 +21,8 @@ export const FileManager: React.FC = () => {
     try {
       setLoading(true);
       setError(null);
-      const serverFiles = await fileService.listFiles();
-      setFiles(serverFiles);
+      const listing = await fileService.listFiles();
+      setFiles(listing.files);
     } catch (err) {
       setError(err instanceof Error ? err.message : "Failed to fetch files");
     } finally {
@@ -122,11 +122,16 @@ export const FileManager: React.FC = () => {
           <div className="text-gray-500">No files available</div>
         ) : (
           <ul className="space-y-2">
-            {files.map((filename) => (
-              <li key={filename} className="flex items-center justify-between">
-                <span className="text-gray-700">{filename}</span>
+            {files.map((file) => (
+              <li key={file.id} className="flex items-center justify-between">
+                <span className="text-gray-700">
+                  {file.original_name}
+                  <span className="ml-2 text-sm text-gray-400">
+                    {Math.ceil(file.size / 1024)} KB
+                  </span>
+                </span>
                 <button
-                  onClick={() => handleDownload(filename)}
+                  onClick={() => handleDownload(file.key)}
                   disabled={isOffline}
                   className="text-blue-500 hover:text-blue-700 disabled:text-gray-400 disabled:cursor-not-allowed"
                 >
@@ -0,0 +1,78 @@
+import React, { useEffect, useState } from "react";
+import AuthService, { AuthUser } from "../services/authService";
+
+const authService = AuthService.getInstance();
+
+// Staff and admins sign in here before managing products, files or the generator
+export const LoginPanel: React.FC = () => {
+  const [user, setUser] = useState<AuthUser | null>(null);
+  const [email, setEmail] = useState("");
+  const [password, setPassword] = useState("");
+  const [error, setError] = useState<string | null>(null);
+  const [submitting, setSubmitting] = useState(false);
+
+  useEffect(() => {
+    setUser(authService.getUser());
+    authService.addListener(setUser);
+    return () => authService.removeListener(setUser);
+  }, []);
+
+  const handleLogin = async (event: React.FormEvent) => {
+    event.preventDefault();
+    setSubmitting(true);
+    setError(null);
+    try {
+      await authService.login(email, password);
+      setPassword("");
+    } catch (err) {
+      setError(err instanceof Error ? err.message : "Failed to sign in");
+    } finally {
+      setSubmitting(false);
+    }
+  };
+
+  if (user) {
+    return (
+      <div className="flex items-center gap-3">
+        <span className="text-sm">
+          {user.email} ({user.role})
+        </span>
+        <button
+          onClick={() => authService.logout()}
+          className="px-3 py-1 bg-white text-blue-500 rounded shadow hover:bg-gray-100"
+        >
+          Sign out
+        </button>
+      </div>
+    );
+  }
+
+  return (
+    <form onSubmit={handleLogin} className="flex items-center gap-2">
+      <input
+        type="email"
+        placeholder="Email"
+        value={email}
+        onChange={(e) => setEmail(e.target.value)}
+        className="px-2 py-1 border rounded text-black"
+        required
+      />
+      <input
+        type="password"
+        placeholder="Password"
+        value={password}
+        onChange={(e) => setPassword(e.target.value)}
+        className="px-2 py-1 border rounded text-black"
+        required
+      />
+      <button
+        type="submit"
+        disabled={submitting}
+        className="px-3 py-1 bg-white text-blue-500 rounded shadow hover:bg-gray-100 disabled:opacity-50"
+      >
+        {submitting ? "Signing in..." : "Sign in"}
+      </button>
+      {error && <span className="text-sm text-red-600">{error}</span>}
+    </form>
+  );
+};
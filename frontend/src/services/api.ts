@@ -1,4 +1,5 @@
 import { Product } from "../types";
+import AuthService from "./authService";
 
 export const API_BASE_URL = "http://localhost:3001";
 
@@ -42,6 +43,7 @@ export const api = {
       method: "POST",
       headers: {
         "Content-Type": "application/json",
+        ...AuthService.getInstance().authHeaders(),
       },
       body: JSON.stringify({
         name: product.name,
@@ -63,6 +65,7 @@ export const api = {
       method: "PUT",
       headers: {
         "Content-Type": "application/json",
+        ...AuthService.getInstance().authHeaders(),
       },
       body: JSON.stringify(product),
     });
@@ -76,6 +79,7 @@ export const api = {
   async deleteProduct(id: number): Promise<void> {
     const response = await fetch(`${API_BASE_URL}/api/products/${id}`, {
       method: "DELETE",
+      headers: AuthService.getInstance().authHeaders(),
     });
     if (!response.ok) {
       const error = await response.json();
@@ -0,0 +1,92 @@
+import { API_BASE_URL } from "./api";
+
+const TOKEN_KEY = "authToken";
+const USER_KEY = "authUser";
+
+export interface AuthUser {
+  id: number;
+  email: string;
+  role: "customer" | "staff" | "admin";
+  created_at: number;
+}
+
+type AuthListener = (user: AuthUser | null) => void;
+
+// Holds the session token from /api/auth/login; product writes, uploads, file
+// management and generator control are all rejected by the backend without it
+export class AuthService {
+  private static instance: AuthService | null = null;
+  private listeners: AuthListener[] = [];
+
+  public static getInstance(): AuthService {
+    if (!AuthService.instance) {
+      AuthService.instance = new AuthService();
+    }
+    return AuthService.instance;
+  }
+
+  public getToken(): string | null {
+    if (typeof window === "undefined") return null;
+    return localStorage.getItem(TOKEN_KEY);
+  }
+
+  public getUser(): AuthUser | null {
+    if (typeof window === "undefined") return null;
+    const stored = localStorage.getItem(USER_KEY);
+    return stored ? JSON.parse(stored) : null;
+  }
+
+  // Headers to merge into any request against a protected route
+  public authHeaders(): Record<string, string> {
+    const token = this.getToken();
+    return token ? { Authorization: `Bearer ${token}` } : {};
+  }
+
+  public async login(email: string, password: string): Promise<AuthUser> {
+    const response = await fetch(`${API_BASE_URL}/api/auth/login`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ email, password }),
+    });
+
+    if (!response.ok) {
+      const errorData = await response.json();
+      throw new Error(errorData.error || "Failed to sign in");
+    }
+
+    const { token, user } = await response.json();
+    localStorage.setItem(TOKEN_KEY, token);
+    localStorage.setItem(USER_KEY, JSON.stringify(user));
+    this.notify(user);
+    return user;
+  }
+
+  public async logout(): Promise<void> {
+    try {
+      await fetch(`${API_BASE_URL}/api/auth/logout`, {
+        method: "POST",
+        headers: this.authHeaders(),
+      });
+    } catch (error) {
+      console.error("Logout error:", error);
+    } finally {
+      localStorage.removeItem(TOKEN_KEY);
+      localStorage.removeItem(USER_KEY);
+      this.notify(null);
+    }
+  }
+
+  public addListener(listener: AuthListener): void {
+    this.listeners.push(listener);
+  }
+
+  public removeListener(listener: AuthListener): void {
+    this.listeners = this.listeners.filter((l) => l !== listener);
+  }
+
+  private notify(user: AuthUser | null): void {
+    this.listeners.forEach((listener) => listener(user));
+  }
+}
+
+export default AuthService;
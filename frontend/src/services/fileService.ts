@@ -1,4 +1,43 @@
 import { API_BASE_URL } from "./api";
+import AuthService from "./authService";
+
+export interface StoredFile {
+  key: string;
+  original_name: string;
+  content_type: string;
+  size: number;
+  sha256: string;
+  // Resized and re-encoded copies, present for JPEG, PNG and WebP uploads
+  variants?: StoredVariant[];
+}
+
+export interface StoredVariant {
+  key: string;
+  size_name: string;
+  width: number;
+  content_type: string;
+  size: number;
+}
+
+// A stored upload as recorded in the file listing
+export interface FileRecord {
+  id: number;
+  key: string;
+  original_name: string;
+  content_type: string;
+  size: number;
+  sha256: string;
+  variant_keys: string[];
+  owner: string;
+  created_at: number;
+}
+
+export interface FileListing {
+  files: FileRecord[];
+  total: number;
+  offset: number;
+  limit: number;
+}
 
 export class FileService {
   private static instance: FileService | null = null;
@@ -17,12 +56,13 @@ export class FileService {
 
   public async uploadFile(
     file: File
-  ): Promise<{ status: string; message: string; filename: string }> {
+  ): Promise<{ status: string; message: string; files: StoredFile[] }> {
     const formData = new FormData();
     formData.append("file", file);
 
     const response = await fetch(`${this.baseUrl}/api/upload`, {
       method: "POST",
+      headers: AuthService.getInstance().authHeaders(),
       body: formData,
     });
 
@@ -73,20 +113,35 @@ export class FileService {
     }
   }
 
-  public async listFiles(): Promise<string[]> {
+  public async listFiles(offset = 0, limit = 50): Promise<FileListing> {
     try {
-      const response = await fetch(`${this.baseUrl}/api/files`);
+      const response = await fetch(
+        `${this.baseUrl}/api/files?offset=${offset}&limit=${limit}`,
+        { headers: AuthService.getInstance().authHeaders() }
+      );
 
       if (!response.ok) {
         const errorData = await response.json();
         throw new Error(errorData.error || "Failed to list files");
       }
 
-      const data = await response.json();
-      return data.files;
+      return response.json();
     } catch (error) {
       console.error("List files error:", error);
       throw error;
     }
   }
+
+  // Fails with the server's message while a product still uses the file
+  public async deleteFile(id: number): Promise<void> {
+    const response = await fetch(`${this.baseUrl}/api/files/${id}`, {
+      method: "DELETE",
+      headers: AuthService.getInstance().authHeaders(),
+    });
+
+    if (!response.ok) {
+      const errorData = await response.json();
+      throw new Error(errorData.error || "Failed to delete file");
+    }
+  }
 }
@@ -1,4 +1,5 @@
 import { Product } from "../types";
+import AuthService from "./authService";
 
 interface PendingOperation {
   type: "CREATE" | "UPDATE" | "DELETE";
@@ -121,7 +122,10 @@ class OfflineService {
     try {
       const response = await fetch("http://localhost:3001/api/products", {
         method: "POST",
-        headers: { "Content-Type": "application/json" },
+        headers: {
+          "Content-Type": "application/json",
+          ...AuthService.getInstance().authHeaders(),
+        },
         body: JSON.stringify(operation.product),
       });
 
@@ -140,7 +144,10 @@ class OfflineService {
         `http://localhost:3001/api/products/${product.id}`,
         {
           method: "PUT",
-          headers: { "Content-Type": "application/json" },
+          headers: {
+            "Content-Type": "application/json",
+            ...AuthService.getInstance().authHeaders(),
+          },
           body: JSON.stringify(product),
         }
       );
@@ -155,6 +162,7 @@ class OfflineService {
     try {
       const response = await fetch(`http://localhost:3001/api/products/${id}`, {
         method: "DELETE",
+        headers: AuthService.getInstance().authHeaders(),
       });
       if (!response.ok) throw new Error("Failed to sync delete");
     } catch (error) {
@@ -1,5 +1,6 @@
 import { Product, ProductFilters, ValidationError } from "../types";
 import OfflineService from "./offlineService";
+import AuthService from "./authService";
 
 const API_BASE_URL = "http://localhost:3001/api";
 const ITEMS_PER_PAGE = 6;
@@ -265,7 +266,10 @@ class ProductService {
     try {
       const response = await fetch(`${API_BASE_URL}/products`, {
         method: "POST",
-        headers: { "Content-Type": "application/json" },
+        headers: {
+          "Content-Type": "application/json",
+          ...AuthService.getInstance().authHeaders(),
+        },
         body: JSON.stringify(product),
       });
 
@@ -325,6 +329,7 @@ class ProductService {
           method: "PUT",
           headers: {
             "Content-Type": "application/json",
+            ...AuthService.getInstance().authHeaders(),
           },
           body: JSON.stringify(product),
         }
@@ -405,6 +410,7 @@ class ProductService {
           method: "DELETE",
           headers: {
             "Content-Type": "application/json",
+            ...AuthService.getInstance().authHeaders(),
           },
         }
       );
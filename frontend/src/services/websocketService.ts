@@ -1,9 +1,41 @@
 import { Product } from "../types";
 
+// Every server message is wrapped in a versioned envelope
+interface ServerMessage {
+  v: number;
+  // Identifies the server run; sequence numbers restart with every run
+  epoch?: string;
+  seq?: number;
+  id?: string;
+  type: string;
+  payload: unknown;
+}
+
+export interface PriceChange {
+  product_id: number;
+  category: string | null;
+  old_price: number | null;
+  new_price: number;
+  source: string;
+  changed_at: number;
+}
+
+// Catalog changes handed to listeners; a snapshot is the full catalog as of connecting
+export type ProductEvent =
+  | { type: "product_created"; product: Product }
+  | { type: "product_updated"; product: Product }
+  | { type: "product_deleted"; product: Product }
+  | { type: "price_changed"; change: PriceChange }
+  | { type: "snapshot"; products: Product[] };
+
+type ProductEventListener = (event: ProductEvent) => void;
+
 class WebSocketService {
   private static instance: WebSocketService;
   private socket: WebSocket | null = null;
-  private listeners: ((product: Product) => void)[] = [];
+  // Last `<epoch>:<seq>` seen, sent on reconnect so only missed events are replayed
+  private lastPosition: string | null = null;
+  private listeners: ProductEventListener[] = [];
 
   private constructor() {}
 
@@ -19,7 +51,8 @@ class WebSocketService {
       return;
     }
 
-    this.socket = new WebSocket("ws://localhost:3001/ws");
+    const since = this.lastPosition === null ? "" : `?since=${this.lastPosition}`;
+    this.socket = new WebSocket(`ws://localhost:3001/ws${since}`);
 
     this.socket.onopen = () => {
       console.log("WebSocket connection established");
@@ -27,8 +60,19 @@ class WebSocketService {
 
     this.socket.onmessage = (event) => {
       try {
-        const product = JSON.parse(event.data);
-        this.notifyListeners(product);
+        const message: ServerMessage = JSON.parse(event.data);
+        if (message.epoch !== undefined && message.seq !== undefined) {
+          this.lastPosition = `${message.epoch}:${message.seq}`;
+        }
+        const productEvent = toProductEvent(message);
+        if (productEvent) {
+          this.notifyListeners(productEvent);
+        } else if (message.type === "resync") {
+          // The server closes after this; reconnecting with lastPosition replays what was dropped
+          console.warn("WebSocket fell behind, resyncing:", message.payload);
+        } else if (message.type === "error") {
+          console.error("WebSocket server error:", message.payload);
+        }
       } catch (error) {
         console.error("Error parsing WebSocket message:", error);
       }
@@ -53,16 +97,34 @@ class WebSocketService {
     }
   }
 
-  public addListener(listener: (product: Product) => void) {
+  public addListener(listener: ProductEventListener) {
     this.listeners.push(listener);
   }
 
-  public removeListener(listener: (product: Product) => void) {
+  public removeListener(listener: ProductEventListener) {
     this.listeners = this.listeners.filter((l) => l !== listener);
   }
 
-  private notifyListeners(product: Product) {
-    this.listeners.forEach((listener) => listener(product));
+  private notifyListeners(event: ProductEvent) {
+    this.listeners.forEach((listener) => listener(event));
+  }
+}
+
+function toProductEvent(message: ServerMessage): ProductEvent | null {
+  switch (message.type) {
+    case "product_created":
+    case "product_updated":
+    case "product_deleted":
+      return { type: message.type, product: message.payload as Product };
+    case "price_changed":
+      return { type: "price_changed", change: message.payload as PriceChange };
+    case "snapshot":
+      return {
+        type: "snapshot",
+        products: (message.payload as { products: Product[] }).products,
+      };
+    default:
+      return null;
   }
 }
 